@@ -0,0 +1,55 @@
+use mpesa_daraja::mpesa::{B2bCommand, Environment, MpesaClient};
+
+#[tokio::main]
+async fn main() {
+
+    // // ideally
+    // dotenv().ok();
+
+    // let consumer_key = env::var("CONSUMER_KEY").expect("Missing CONSUMER_KEY");
+    // let consumer_secret = env::var("CONSUMER_SECRET").expect("Missing CONSUMER_SECRET");
+    // let initiator_password = env::var("INITIATOR_PASSWORD").expect("Missing INITIATOR_PASSWORD");
+    // let cert_path = env::var("CERT_PATH").unwrap_or("src/certs/production.cer".to_string());
+    // let short_code = env::var("SHORT_CODE").expect("Missing SHORT_CODE");
+    // let initiator_name = env::var("INITIATOR_NAME").expect("Missing INITIATOR_NAME");
+    // let result_url = env::var("RESULT_URL").expect("Missing RESULT_URL");
+    // let queue_timeout_url = env::var("QUEUE_TIMEOUT_URL").expect("Missing QUEUE_TIMEOUT_URL");
+
+    // hardcoded for testing
+    let consumer_key = "xxxxxxxxxxxxxxxxxxxx";
+    let consumer_secret = "xxxxxxxxxxxxxxxxxxxx";
+    let initiator_password = "xxxxxxx";
+    let cert_path = "src/certs/production.cer";
+    let short_code = "xxxxxxx";
+    let initiator_name = "xxxxxx";
+    let result_url = "https://xxxxxx";
+    let queue_timeout_url = "https://xxxxxx";
+
+    let client = MpesaClient::new(consumer_key, consumer_secret, Environment::Production); // or Environment::Sandbox
+
+    let security_credential = MpesaClient::generate_security_credential(initiator_password, true, Some(cert_path))
+        .expect("Failed to generate security credential");
+
+    match client
+        .b2b_payment(
+            B2bCommand::BusinessPayBill,
+            1, // Amount
+            "000000", // receiver paybill
+            "ACCOUNT-REF",
+            None, // requester
+            "Test B2B Payment",
+            result_url,
+            queue_timeout_url,
+            initiator_name,
+            &security_credential,
+            short_code,
+        )
+        .await
+    {
+        Ok(response) => {
+            println!("B2B Response: {}", response.response_description);
+            println!("Conversation ID: {:?}", response.conversation_id);
+        }
+        Err(e) => println!("Error making payment: {}", e),
+    }
+}
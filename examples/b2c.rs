@@ -1,5 +1,5 @@
 
-use mpesa_daraja::mpesa::MpesaClient;
+use mpesa_daraja::mpesa::{Environment, MpesaClient};
 
 #[tokio::main]
 async fn main() {
@@ -26,7 +26,7 @@ async fn main() {
     let result_url = "https://xxxxxx";
     let queue_timeout_url = "https://xxxxxx";
 
-    let client = MpesaClient::new(consumer_key, consumer_secret, "production"); // or "sandbox"
+    let client = MpesaClient::new(consumer_key, consumer_secret, Environment::Production); // or Environment::Sandbox
 
     match MpesaClient::generate_security_credential(initiator_password, true, Some(cert_path)) { // true for production // None in place of Some(cert_path) defaults to default path in the generate_security_credential function
         Ok(security_credential) => {
@@ -1,4 +1,4 @@
-use mpesa_daraja::mpesa::MpesaClient;
+use mpesa_daraja::mpesa::{Environment, IdentifierType, MpesaClient};
 
 #[tokio::main]
 async fn main() {
@@ -25,7 +25,7 @@ async fn main() {
     let result_url = "https://xxxxxx.xx/xxx";
     let queue_timeout_url = "https://xxxxx.xx/xxx";
 
-    let client = MpesaClient::new(consumer_key, consumer_secret, "production");
+    let client = MpesaClient::new(consumer_key, consumer_secret, Environment::Production);
 
     let security_credential = MpesaClient::generate_security_credential(initiator_password, true, Some(cert_path))
         .expect("Failed to generate security credential");
@@ -35,6 +35,7 @@ async fn main() {
         initiator_name,
         &security_credential,
         short_code,
+        IdentifierType::Shortcode, // or IdentifierType::TillNumber
         "Balance Inquiry",
         queue_timeout_url,
         result_url,
@@ -0,0 +1,36 @@
+use mpesa_daraja::mpesa::{C2bResponseType, C2bVersion, Environment, MpesaClient};
+
+#[tokio::main]
+async fn main() {
+
+    // // ideally
+    // dotenv().ok();
+
+    // let consumer_key = env::var("CONSUMER_KEY").expect("Missing CONSUMER_KEY");
+    // let consumer_secret = env::var("CONSUMER_SECRET").expect("Missing CONSUMER_SECRET");
+    // let short_code = env::var("SHORT_CODE").expect("Missing SHORT_CODE");
+    // let confirmation_url = env::var("CONFIRMATION_URL").expect("Missing CONFIRMATION_URL");
+    // let validation_url = env::var("VALIDATION_URL").expect("Missing VALIDATION_URL");
+
+    // hardcoded for testing
+    let consumer_key = "xxxxxxxxxx";
+    let consumer_secret = "xxxxxxxxxxxxx";
+    let short_code = "xxxxxxx";
+    let confirmation_url = "https://xxxxxxxxx.xx/confirmation";
+    let validation_url = "https://xxxxxxxxx.xx/validation";
+
+    let client = MpesaClient::new(consumer_key, consumer_secret, Environment::Production); // or Environment::Sandbox
+    match client
+        .register_c2b_urls(
+            Some(short_code),
+            C2bResponseType::Completed,
+            confirmation_url,
+            validation_url,
+            C2bVersion::V2,
+        )
+        .await
+    {
+        Ok(response) => println!("Register URL Response: {}", response.response_description),
+        Err(e) => println!("Error registering URLs: {}", e),
+    }
+}
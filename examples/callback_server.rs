@@ -0,0 +1,43 @@
+// Run with: cargo run --example callback_server --features server
+use mpesa_daraja::mpesa::server::{router, CallbackHandler, CallbackPaths};
+use mpesa_daraja::mpesa::{B2cOutcome, B2cResult, StkCallback, StkOutcome};
+
+struct Handler;
+
+impl CallbackHandler for Handler {
+    async fn on_stk_callback(&self, callback: StkCallback) {
+        match callback.outcome() {
+            Ok(StkOutcome::Paid(payment)) => {
+                println!("Paid {} by {}: {}", payment.amount, payment.phone_number, payment.mpesa_receipt_number)
+            }
+            Ok(StkOutcome::Confirmed(confirmation)) => {
+                println!("Paid {} (confirmed by query)", confirmation.checkout_request_id)
+            }
+            Ok(StkOutcome::Failed(failure)) => println!("STK Push failed: {:?}", failure.reason),
+            Err(e) => println!("Error: {}", e),
+        }
+    }
+
+    async fn on_b2c_result(&self, result: B2cResult) {
+        match result.outcome() {
+            Ok(B2cOutcome::Paid(payment)) => {
+                println!("Sent {} to {}", payment.transaction_amount, payment.transaction_receipt)
+            }
+            Ok(B2cOutcome::Failed(failure)) => println!("B2C failed: {}", failure.result_desc),
+            Err(e) => println!("Error: {}", e),
+        }
+    }
+}
+
+#[tokio::main]
+async fn main() {
+    // The paths must match the CallBackURL / ResultURL passed to Daraja
+    let paths = CallbackPaths::default()
+        .stk("/mpesa/stk/callback")
+        .b2c_result("/mpesa/b2c/result");
+
+    let app = router(Handler, paths);
+    let listener = tokio::net::TcpListener::bind("0.0.0.0:8080").await.unwrap();
+    println!("Listening for M-Pesa callbacks on {}", listener.local_addr().unwrap());
+    axum::serve(listener, app).await.unwrap();
+}
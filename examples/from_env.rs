@@ -0,0 +1,30 @@
+use mpesa_daraja::mpesa::{MpesaClient, StkPushRequest};
+
+#[tokio::main]
+async fn main() {
+
+    // Reads MPESA_CONSUMER_KEY, MPESA_CONSUMER_SECRET, MPESA_ENVIRONMENT, MPESA_SHORT_CODE,
+    // MPESA_PASSKEY, MPESA_CALLBACK_URL, ... (see MpesaClient::from_env).
+    // MpesaClient::from_config("mpesa.toml") reads the same settings from a file.
+    let client = match MpesaClient::from_env() {
+        Ok(client) => client,
+        Err(e) => {
+            println!("{}", e);
+            return;
+        }
+    };
+
+    // Shortcode, passkey and callback URL come from the client's profile.
+    let request = StkPushRequest::builder()
+        .phone_number("2547XXXXXXXX")
+        .amount(1)
+        .account_reference("TestRef")
+        .transaction_desc("Test STK Push in Rust")
+        .build()
+        .expect("invalid request");
+
+    match client.send_stk_push(&request).await {
+        Ok(resp) => println!("STK Push Response: {}", resp.response_description),
+        Err(e) => println!("Error: {}", e),
+    }
+}
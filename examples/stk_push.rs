@@ -1,4 +1,4 @@
-use mpesa_daraja::mpesa::MpesaClient;
+use mpesa_daraja::mpesa::{Environment, MpesaClient};
 
 #[tokio::main]
 async fn main() {
@@ -19,7 +19,7 @@ async fn main() {
     let passkey = "xxxxxxxxxxxxxx";
     let callback_url = "https://xxxxxxxxx.xx/xxxxxxx";
 
-    let client = MpesaClient::new(consumer_key, consumer_secret, "production"); // or sandbox
+    let client = MpesaClient::new(consumer_key, consumer_secret, Environment::Production); // or Environment::Sandbox
     let response = client
         .stk_push(
             "2547XXXXXXXX", // phone_number,
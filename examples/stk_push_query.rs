@@ -0,0 +1,36 @@
+use mpesa_daraja::mpesa::{Environment, MpesaClient};
+
+#[tokio::main]
+async fn main() {
+
+    // // ideally
+    // dotenv().ok();
+
+    // let consumer_key = env::var("CONSUMER_KEY").expect("Missing CONSUMER_KEY");
+    // let consumer_secret = env::var("CONSUMER_SECRET").expect("Missing CONSUMER_SECRET");
+    // let short_code = env::var("SHORT_CODE").expect("Missing SHORT_CODE");
+    // let passkey = env::var("PASS_KEY").expect("Missing PASS_KEY");
+
+    // hardcoded for testing
+    let consumer_key = "xxxxxxxxxx";
+    let consumer_secret = "xxxxxxxxxxxxx";
+    let short_code = "xxxxxxxxxxxxx";
+    let passkey = "xxxxxxxxxxxxxx";
+
+    let client = MpesaClient::new(consumer_key, consumer_secret, Environment::Production); // or Environment::Sandbox
+    let response = client
+        .stk_push_query(
+            "ws_CO_XXXXXXXXXXXXXXXXXXXXXXXXX", // CheckoutRequestID from stk_push
+            Some(short_code),
+            Some(passkey),
+        )
+        .await;
+
+    match response {
+        Ok(resp) => {
+            println!("STK Push Query Result: {} - {}", resp.result_code, resp.result_desc);
+            println!("Paid: {}", resp.is_paid());
+        }
+        Err(e) => println!("Error: {}", e),
+    }
+}
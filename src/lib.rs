@@ -1,12 +1,25 @@
 // src/lib.rs
-use reqwest::Client;
-use serde::{Deserialize, Serialize};
+use reqwest::header::{AUTHORIZATION, CONTENT_TYPE};
+use reqwest::{Client, Method, StatusCode};
+use serde::{de::DeserializeOwned, Deserialize, Deserializer, Serialize};
 use base64::{Engine as _, engine::general_purpose};
-use chrono::Utc;
+use chrono::{DateTime, NaiveDateTime, Utc};
 use std::error::Error;
+use std::fmt;
+use std::collections::HashMap;
 use std::fs;
+use std::future::Future;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::{oneshot, Mutex};
 use openssl::x509::X509;
 use openssl::rsa::Padding;
+use openssl::error::ErrorStack;
+
+/// Re-exported so a client passed to [`mpesa::MpesaClientBuilder::http_client`]
+/// comes from the same `reqwest` version as this crate.
+pub use reqwest;
 
 /// MPESA Daraja API client library.
 ///
@@ -15,542 +28,6539 @@ use openssl::rsa::Padding;
 pub mod mpesa {
     use super::*;
 
+    /// The Daraja deployment a client talks to.
+    #[derive(Debug, Clone, PartialEq, Eq, Default)]
+    pub enum Environment {
+        /// `https://sandbox.safaricom.co.ke`.
+        #[default]
+        Sandbox,
+        /// `https://api.safaricom.co.ke`; moves real money.
+        Production,
+        /// Any other base URL, e.g. a local mock server in tests.
+        Custom(String),
+    }
+
+    impl Environment {
+        /// The base URL requests are sent to, without a trailing slash.
+        pub fn base_url(&self) -> &str {
+            match self {
+                Environment::Sandbox => "https://sandbox.safaricom.co.ke",
+                Environment::Production => "https://api.safaricom.co.ke",
+                Environment::Custom(base_url) => base_url.trim_end_matches('/'),
+            }
+        }
+    }
+
+    impl std::str::FromStr for Environment {
+        type Err = MpesaError;
+
+        /// Parses `"sandbox"` or `"production"` (any case), or an `http(s)://` base
+        /// URL as [`Environment::Custom`]. Anything else is an error rather than a
+        /// silent fallback to production.
+        fn from_str(value: &str) -> Result<Self, Self::Err> {
+            let value = value.trim();
+            if value.eq_ignore_ascii_case("sandbox") {
+                Ok(Environment::Sandbox)
+            } else if value.eq_ignore_ascii_case("production") {
+                Ok(Environment::Production)
+            } else if value.starts_with("http://") || value.starts_with("https://") {
+                Ok(Environment::Custom(value.to_string()))
+            } else {
+                Err(MpesaError::Config(format!(
+                    "unknown environment {:?}, expected \"sandbox\", \"production\" or a base URL",
+                    value
+                )))
+            }
+        }
+    }
+
     /// A client for interacting with Safaricom's MPESA Daraja API.
-    pub struct MpesaClient {
+    ///
+    /// Requests go through a [`Transport`], by default [`ReqwestTransport`], and STK
+    /// Pushes and B2C payments are recorded in a [`TransactionStore`], by default
+    /// none. Clones share the same transport, store and access token cache, so a
+    /// client can be created once and cloned into each task or request handler.
+    pub struct MpesaClient<T = ReqwestTransport, S = NoStore> {
+        inner: Arc<ClientInner<T, S>>,
+    }
+
+    impl<T, S> Clone for MpesaClient<T, S> {
+        fn clone(&self) -> Self {
+            MpesaClient {
+                inner: Arc::clone(&self.inner),
+            }
+        }
+    }
+
+    struct ClientInner<T, S> {
         consumer_key: String,
         consumer_secret: String,
-        environment: String,
+        environment: Environment,
+        profile: BusinessProfile,
+        transport: T,
+        store: S,
+        token: Mutex<Option<CachedToken>>,
+        callbacks: CallbackRegistry,
     }
 
-    #[derive(Deserialize)]
-    struct AccessTokenResponse {
-        access_token: String,
-        #[allow(dead_code)]
-        expires_in: String,
+    /// Builder for [`MpesaClient`], created with [`MpesaClient::builder`].
+    #[derive(Clone)]
+    pub struct MpesaClientBuilder<T = ReqwestTransport, S = NoStore> {
+        consumer_key: String,
+        consumer_secret: String,
+        environment: Environment,
+        profile: BusinessProfile,
+        transport: T,
+        store: S,
     }
 
-    // STK Push (C2B)
-    #[derive(Serialize)]
-    struct StkPushRequest {
-        #[serde(rename = "BusinessShortCode")]
-        business_short_code: String,
-        #[serde(rename = "Password")]
-        password: String,
-        #[serde(rename = "Timestamp")]
-        timestamp: String,
-        #[serde(rename = "TransactionType")]
-        transaction_type: String,
-        #[serde(rename = "Amount")]
-        amount: String,
-        #[serde(rename = "PartyA")]
-        party_a: String,
-        #[serde(rename = "PartyB")]
-        party_b: String,
-        #[serde(rename = "PhoneNumber")]
-        phone_number: String,
-        #[serde(rename = "CallBackURL")]
-        callback_url: String,
-        #[serde(rename = "AccountReference")]
-        account_reference: String,
-        #[serde(rename = "TransactionDesc")]
-        transaction_desc: String,
+    impl<T: fmt::Debug, S: fmt::Debug> fmt::Debug for MpesaClientBuilder<T, S> {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            f.debug_struct("MpesaClientBuilder")
+                .field("consumer_key", &self.consumer_key)
+                .field("consumer_secret", &REDACTED)
+                .field("environment", &self.environment)
+                .field("profile", &self.profile)
+                .field("transport", &self.transport)
+                .field("store", &self.store)
+                .finish()
+        }
     }
 
-    /// Response from an STK Push (C2B) request.
-    #[derive(Deserialize)]
-    pub struct StkPushResponse {
-        #[serde(rename = "MerchantRequestID")]
-        pub merchant_request_id: Option<String>,
-        #[serde(rename = "CheckoutRequestID")]
-        pub checkout_request_id: Option<String>,
-        #[serde(rename = "ResponseCode")]
-        pub response_code: String,
-        #[serde(rename = "ResponseDescription")]
-        pub response_description: String,
-        #[serde(rename = "CustomerMessage")]
-        pub customer_message: Option<String>,
+    /// Printed in place of secrets in `Debug` output.
+    const REDACTED: &str = "<redacted>";
+
+    /// Hides a secret in `Debug` output while still showing whether it is set.
+    fn redact(secret: &Option<String>) -> Option<&'static str> {
+        secret.as_ref().map(|_| REDACTED)
     }
 
-    /// Error response from any MPESA API call.
-    #[derive(Deserialize, Debug)]
-    pub struct ErrorResponse {
-        #[serde(rename = "responseId", alias = "requestId")]
-        pub response_id: Option<String>,        // Support both field names with Option
-    
-        #[serde(rename = "errorCode")]
-        pub error_code: String,
-    
-        #[serde(rename = "errorMessage")]
-        pub error_message: String,
+    impl<T: Transport, S: TransactionStore> MpesaClientBuilder<T, S> {
+        /// The deployment to talk to; defaults to [`Environment::Sandbox`].
+        pub fn environment(mut self, environment: Environment) -> Self {
+            self.environment = environment;
+            self
+        }
+
+        /// Business details that requests fall back to when they leave them unset.
+        pub fn profile(mut self, profile: BusinessProfile) -> Self {
+            self.profile = profile;
+            self
+        }
+
+        /// Sends requests through `transport` instead of `reqwest`, e.g. an
+        /// in-memory one in tests.
+        pub fn transport<U: Transport>(self, transport: U) -> MpesaClientBuilder<U, S> {
+            MpesaClientBuilder {
+                consumer_key: self.consumer_key,
+                consumer_secret: self.consumer_secret,
+                environment: self.environment,
+                profile: self.profile,
+                transport,
+                store: self.store,
+            }
+        }
+
+        /// Records STK Pushes and B2C payments in `store`, updating them as their
+        /// callbacks arrive.
+        pub fn store<U: TransactionStore>(self, store: U) -> MpesaClientBuilder<T, U> {
+            MpesaClientBuilder {
+                consumer_key: self.consumer_key,
+                consumer_secret: self.consumer_secret,
+                environment: self.environment,
+                profile: self.profile,
+                transport: self.transport,
+                store,
+            }
+        }
+
+        /// Creates the client.
+        pub fn build(self) -> MpesaClient<T, S> {
+            MpesaClient {
+                inner: Arc::new(ClientInner {
+                    consumer_key: self.consumer_key,
+                    consumer_secret: self.consumer_secret,
+                    environment: self.environment,
+                    profile: self.profile,
+                    transport: self.transport,
+                    store: self.store,
+                    token: Mutex::new(None),
+                    callbacks: CallbackRegistry::default(),
+                }),
+            }
+        }
     }
 
-    // B2C
-    #[derive(Serialize)]
-    struct B2cRequest {
-        #[serde(rename = "InitiatorName")]
-        initiator_name: String,
-        #[serde(rename = "SecurityCredential")]
-        security_credential: String,
-        #[serde(rename = "CommandID")]
-        command_id: String,
-        #[serde(rename = "Amount")]
-        amount: String,
-        #[serde(rename = "PartyA")]
-        party_a: String,
-        #[serde(rename = "PartyB")]
-        party_b: String,
-        #[serde(rename = "Remarks")]
-        remarks: String,
-        #[serde(rename = "QueueTimeOutURL")]
-        queue_timeout_url: String,
-        #[serde(rename = "ResultURL")]
-        result_url: String,
-        #[serde(rename = "Occasion")]
-        occasion: String,
+    impl<S> MpesaClientBuilder<ReqwestTransport, S> {
+        /// A preconfigured HTTP client to send requests with, e.g. one with a proxy,
+        /// timeouts or custom root certificates; defaults to `reqwest::Client::new()`.
+        ///
+        /// # Examples
+        /// ```
+        /// use std::time::Duration;
+        /// use mpesa_daraja::mpesa::MpesaClient;
+        /// use mpesa_daraja::reqwest;
+        ///
+        /// let http = reqwest::Client::builder()
+        ///     .timeout(Duration::from_secs(30))
+        ///     .build()
+        ///     .unwrap();
+        /// let client = MpesaClient::builder("consumer_key", "consumer_secret")
+        ///     .http_client(http)
+        ///     .build();
+        /// ```
+        pub fn http_client(mut self, http: Client) -> Self {
+            self.transport = ReqwestTransport::new(http);
+            self
+        }
     }
 
-    /// Response from a B2C payment request.
-    #[derive(Deserialize)]
-    pub struct B2cResponse {
-        #[serde(rename = "ConversationID")]
-        pub conversation_id: Option<String>,
-        #[serde(rename = "OriginatorConversationID")]
-        pub originator_conversation_id: Option<String>,
-        #[serde(rename = "ResponseCode")]
-        pub response_code: String,
-        #[serde(rename = "ResponseDescription")]
-        pub response_description: String,
+    /// An HTTP request ready to be sent to Daraja.
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub struct HttpRequest {
+        /// `GET` for the access token, `POST` for every API call.
+        pub method: Method,
+        /// The full URL, including the environment's base URL.
+        pub url: String,
+        /// The `Authorization` header value, `Basic ...` or `Bearer ...`.
+        pub authorization: String,
+        /// The JSON body, if any.
+        pub body: Option<String>,
     }
 
-    // Balance Check
-    #[derive(Serialize, Deserialize)]
-    struct BalanceRequest {
-        #[serde(rename = "Initiator")]
-        initiator: String,
-        #[serde(rename = "SecurityCredential")]
-        security_credential: String,
-        #[serde(rename = "CommandID")]
-        command_id: String,
-        #[serde(rename = "PartyA")]
-        party_a: String,
-        #[serde(rename = "IdentifierType")]
-        identifier_type: String,
-        #[serde(rename = "Remarks")]
-        remarks: String,
-        #[serde(rename = "QueueTimeOutURL")]
-        queue_timeout_url: String,
-        #[serde(rename = "ResultURL")]
-        result_url: String,
+    /// The status and raw body Daraja answered an [`HttpRequest`] with.
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub struct HttpResponse {
+        pub status: StatusCode,
+        pub body: String,
     }
 
-    /// Immediate response from a balance query.
-    #[derive(Serialize, Deserialize)]
-    pub struct BalanceQueryResponse {
-        #[serde(rename = "ConversationID")]
-        pub conversation_id: Option<String>,
-        #[serde(rename = "OriginatorConversationID")]
-        pub originator_conversation_id: Option<String>,
-        #[serde(rename = "ResponseCode")]
-        pub response_code: String,
-        #[serde(rename = "ResponseDescription")]
-        pub response_description: String,
+    /// Sends the HTTP requests made by an [`MpesaClient`].
+    ///
+    /// Implementations only move bytes: status handling, token refreshes and
+    /// decoding are left to the client, so any response should be returned as is.
+    /// Failures to send should be reported as [`MpesaError::Transport`], as
+    /// [`ReqwestTransport`] does.
+    ///
+    /// # Examples
+    /// An in-memory transport that records requests and returns canned responses:
+    /// ```
+    /// use std::sync::{Arc, Mutex};
+    /// use mpesa_daraja::mpesa::{HttpRequest, HttpResponse, MpesaClient, MpesaError, Transport};
+    /// use mpesa_daraja::reqwest::StatusCode;
+    ///
+    /// #[derive(Clone, Default)]
+    /// struct Canned {
+    ///     sent: Arc<Mutex<Vec<HttpRequest>>>,
+    /// }
+    ///
+    /// impl Transport for Canned {
+    ///     async fn send(&self, request: HttpRequest) -> Result<HttpResponse, MpesaError> {
+    ///         let body = if request.url.contains("/oauth/") {
+    ///             r#"{"access_token":"token","expires_in":"3599"}"#
+    ///         } else {
+    ///             r#"{"MerchantRequestID":"1","CheckoutRequestID":"ws_CO_1","ResponseCode":"0",
+    ///                 "ResponseDescription":"Success","CustomerMessage":"Success"}"#
+    ///         };
+    ///         self.sent.lock().unwrap().push(request);
+    ///         Ok(HttpResponse { status: StatusCode::OK, body: body.to_string() })
+    ///     }
+    /// }
+    ///
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), MpesaError> {
+    /// let transport = Canned::default();
+    /// let client = MpesaClient::builder("consumer_key", "consumer_secret")
+    ///     .transport(transport.clone())
+    ///     .build();
+    ///
+    /// let response = client
+    ///     .stk_push("254712345678", 10, "INV-1", "Invoice", "https://example.com/cb", "174379", "passkey")
+    ///     .await?;
+    /// assert_eq!(response.checkout_request_id.as_deref(), Some("ws_CO_1"));
+    ///
+    /// let sent = transport.sent.lock().unwrap();
+    /// let body: serde_json::Value = serde_json::from_str(sent[1].body.as_deref().unwrap()).unwrap();
+    /// assert_eq!(sent[1].authorization, "Bearer token");
+    /// assert_eq!(body["PhoneNumber"], "254712345678");
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub trait Transport: Send + Sync + 'static {
+        /// Sends `request` and returns Daraja's response, whatever its status.
+        fn send(
+            &self,
+            request: HttpRequest,
+        ) -> impl Future<Output = Result<HttpResponse, MpesaError>> + Send;
     }
 
-    /// Callback response for balance and transaction status queries.
-    // Shared Callback Structs (for Balance and Transaction Status)
-    #[derive(Serialize, Deserialize)]
-    pub struct BalanceResponse { // shared
-        #[serde(rename = "ResultType")]
-        pub result_type: i32,
-        #[serde(rename = "ResultCode")]
-        pub result_code: String,
-        #[serde(rename = "ResultDesc")]
-        pub result_desc: String,
-        #[serde(rename = "OriginatorConversationID")]
-        pub originator_conversation_id: String,
-        #[serde(rename = "ConversationID")]
-        pub conversation_id: String,
-        #[serde(rename = "TransactionID")]
-        pub transaction_id: String,
-        #[serde(rename = "ResultParameters")]
-        pub result_parameters: ResultParameters,
+    /// The default [`Transport`], sending requests with a pooled `reqwest::Client`.
+    #[derive(Debug, Clone, Default)]
+    pub struct ReqwestTransport {
+        client: Client,
     }
 
-    /// Parameters in a callback response.
-    #[derive(Serialize, Deserialize)]
-    pub struct ResultParameters { // shared
-        #[serde(rename = "ResultParameter")]
-        pub result_parameter: Vec<ResultParameter>,
+    impl ReqwestTransport {
+        /// Wraps a preconfigured `reqwest::Client`.
+        pub fn new(client: Client) -> Self {
+            ReqwestTransport { client }
+        }
     }
 
-    /// Key-value pair in a callback response.
-    #[derive(Serialize, Deserialize)]
-    pub struct ResultParameter { // shared
-        #[serde(rename = "Key")]
-        pub key: String,
-        #[serde(rename = "Value")]
-        pub value: String,
+    impl Transport for ReqwestTransport {
+        async fn send(&self, request: HttpRequest) -> Result<HttpResponse, MpesaError> {
+            let mut builder = self
+                .client
+                .request(request.method, &request.url)
+                .header(AUTHORIZATION, request.authorization);
+            if let Some(body) = request.body {
+                builder = builder.header(CONTENT_TYPE, "application/json").body(body);
+            }
+
+            let transport_error = |err: reqwest::Error| MpesaError::Transport(err.into());
+            let response = builder.send().await.map_err(transport_error)?;
+            let status = response.status();
+            let body = response.text().await.map_err(transport_error)?;
+            Ok(HttpResponse { status, body })
+        }
     }
 
-    // Transaction Status
-    #[derive(Serialize)]
-    struct TransactionStatusRequest {
-        #[serde(rename = "Initiator")]
-        initiator: String,
-        #[serde(rename = "SecurityCredential")]
-        security_credential: String,
-        #[serde(rename = "CommandID")]
-        command_id: String,
-        #[serde(rename = "TransactionID")]
-        transaction_id: String,
-        #[serde(rename = "PartyA")]
-        party_a: String,
-        #[serde(rename = "IdentifierType")]
-        identifier_type: String,
-        #[serde(rename = "ResultURL")]
-        result_url: String,
-        #[serde(rename = "QueueTimeOutURL")]
-        queue_timeout_url: String,
-        #[serde(rename = "Remarks")]
-        remarks: String,
-        #[serde(rename = "Occasion")]
-        occasion: String,
+    /// Hands callbacks to the requests awaiting them, see
+    /// [`StkPushResponse::wait_for_result`], [`B2cResponse::wait_for_result`] and
+    /// [`TransactionStatusResponse::wait_for_result`].
+    ///
+    /// Every [`MpesaClient`] owns one, available from [`MpesaClient::callbacks`].
+    /// Whatever receives Daraja's callbacks feeds it with
+    /// [`resolve_stk`](CallbackRegistry::resolve_stk),
+    /// [`resolve_b2c`](CallbackRegistry::resolve_b2c) and
+    /// [`resolve_transaction_status`](CallbackRegistry::resolve_transaction_status);
+    /// with the `server` feature it can also be passed to `server::router` directly.
+    #[derive(Clone, Default)]
+    pub struct CallbackRegistry {
+        stk: Arc<Waiters<StkCallback>>,
+        b2c: Arc<Waiters<B2cResult>>,
+        status: Arc<Waiters<TransactionStatusResult>>,
     }
 
-    /// Immediate response from a transaction status query.
-    #[derive(Deserialize)]
-    pub struct TransactionStatusResponse {
-        #[serde(rename = "ConversationID")]
-        pub conversation_id: Option<String>,
-        #[serde(rename = "OriginatorConversationID")]
-        pub originator_conversation_id: Option<String>,
-        #[serde(rename = "ResponseCode")]
-        pub response_code: String,
-        #[serde(rename = "ResponseDescription")]
-        pub response_description: String,
+    impl CallbackRegistry {
+        /// Passes an STK Push callback to the request waiting on its
+        /// `CheckoutRequestID`. Returns `false` if nothing was waiting.
+        pub fn resolve_stk(&self, callback: &StkCallback) -> bool {
+            self.stk.resolve(&callback.checkout_request_id, callback.clone())
+        }
+
+        /// Passes a B2C result to the payment waiting on its
+        /// `OriginatorConversationID` or `ConversationID`. Returns `false` if
+        /// nothing was waiting.
+        pub fn resolve_b2c(&self, result: &B2cResult) -> bool {
+            self.b2c
+                .resolve(&result.originator_conversation_id, result.clone())
+                || self.b2c.resolve(&result.conversation_id, result.clone())
+        }
+
+        /// Passes a transaction status result to the query waiting on its
+        /// `OriginatorConversationID` or `ConversationID`. Returns `false` if
+        /// nothing was waiting.
+        pub fn resolve_transaction_status(&self, result: &TransactionStatusResult) -> bool {
+            self.status
+                .resolve(&result.originator_conversation_id, result.clone())
+                || self.status.resolve(&result.conversation_id, result.clone())
+        }
+    }
+
+    /// Receives decoded callbacks from the `server` feature's router or an
+    /// [`StkReconciler`], and the B2C payments settled by a [`B2cRecovery`]. Every
+    /// method defaults to ignoring its input, so implementations only override the
+    /// ones they expect.
+    ///
+    /// The router acknowledges Daraja once the method returns, so long-running
+    /// work should be handed off rather than awaited here.
+    pub trait CallbackHandler: Send + Sync + 'static {
+        /// Called with the result of an STK Push, including those an
+        /// [`StkReconciler`] found by query; see [`StkCallback::reconciled`].
+        fn on_stk_callback(&self, callback: StkCallback) -> impl Future<Output = ()> + Send {
+            let _ = callback;
+            async {}
+        }
+
+        /// Called with the result of a B2C payment.
+        fn on_b2c_result(&self, result: B2cResult) -> impl Future<Output = ()> + Send {
+            let _ = result;
+            async {}
+        }
+
+        /// Called with the result of a balance query.
+        fn on_balance_result(&self, result: BalanceResponse) -> impl Future<Output = ()> + Send {
+            let _ = result;
+            async {}
+        }
+
+        /// Called with the result of a transaction status query.
+        fn on_transaction_status_result(
+            &self,
+            result: TransactionStatusResult,
+        ) -> impl Future<Output = ()> + Send {
+            let _ = result;
+            async {}
+        }
+
+        /// Called with whatever Daraja posts to a B2C `QueueTimeOutURL`. The payment
+        /// stays pending until its result arrives or a [`B2cRecovery`] settles it.
+        fn on_b2c_timeout(&self, payload: serde_json::Value) -> impl Future<Output = ()> + Send {
+            let _ = payload;
+            async {}
+        }
+
+        /// Called when a [`B2cRecovery`] settles a B2C payment, with its updated record.
+        fn on_b2c_recovered(&self, record: TransactionRecord) -> impl Future<Output = ()> + Send {
+            let _ = record;
+            async {}
+        }
+    }
+
+    /// Resolves waiting STK Push and B2C requests, for when nothing else needs to
+    /// see the callbacks. Handlers that do should call
+    /// [`CallbackRegistry::resolve_stk`] and [`CallbackRegistry::resolve_b2c`]
+    /// themselves.
+    impl CallbackHandler for CallbackRegistry {
+        async fn on_stk_callback(&self, callback: StkCallback) {
+            self.resolve_stk(&callback);
+        }
+
+        async fn on_b2c_result(&self, result: B2cResult) {
+            self.resolve_b2c(&result);
+        }
+
+        async fn on_transaction_status_result(&self, result: TransactionStatusResult) {
+            self.resolve_transaction_status(&result);
+        }
+    }
+
+    /// Records STK Push and B2C callbacks in the client's store and resolves
+    /// the requests waiting on them, see [`MpesaClient::complete_stk_push`]. Transaction
+    /// status results resolve the queries waiting on them, e.g. those of a [`B2cRecovery`].
+    impl<T: Transport, S: TransactionStore> CallbackHandler for MpesaClient<T, S> {
+        async fn on_stk_callback(&self, callback: StkCallback) {
+            if let Err(err) = self.complete_stk_push(&callback).await {
+                tracing::error!("Failed to complete STK Push {}: {}", callback.checkout_request_id, err);
+            }
+        }
+
+        async fn on_b2c_result(&self, result: B2cResult) {
+            if let Err(err) = self.complete_b2c_payment(&result).await {
+                tracing::error!(
+                    "Failed to complete B2C payment {}: {}",
+                    result.originator_conversation_id,
+                    err
+                );
+            }
+        }
+
+        async fn on_transaction_status_result(&self, result: TransactionStatusResult) {
+            self.complete_transaction_status(&result);
+        }
     }
 
-    impl MpesaClient {
-        /// Creates a new MPESA client.
-        ///
-        /// # Arguments
-        /// * `consumer_key` - The API consumer key from Safaricom.
-        /// * `consumer_secret` - The API consumer secret from Safaricom.
-        /// * `environment` - `"sandbox"` or `"production"`.
-        ///
-        /// # Examples
-        /// ```
-        /// use mpesa_daraja::mpesa::MpesaClient;
-        /// let client = MpesaClient::new("consumer_key", "consumer_secret", "sandbox");
-        /// ```
-        pub fn new(consumer_key: &str, consumer_secret: &str, environment: &str) -> Self {
-            MpesaClient {
-                consumer_key: consumer_key.to_string(),
-                consumer_secret: consumer_secret.to_string(),
-                environment: environment.to_string(),
+    /// Senders for requests awaiting a callback, keyed by request ID.
+    struct Waiters<T> {
+        senders: std::sync::Mutex<HashMap<String, oneshot::Sender<T>>>,
+    }
+
+    impl<T> Default for Waiters<T> {
+        fn default() -> Self {
+            Waiters {
+                senders: std::sync::Mutex::new(HashMap::new()),
+            }
+        }
+    }
+
+    impl<T> Waiters<T> {
+        fn register(self: &Arc<Self>, id: String) -> PendingResult<T> {
+            let (sender, receiver) = oneshot::channel();
+            self.lock().insert(id.clone(), sender);
+            PendingResult {
+                id,
+                receiver,
+                waiters: Arc::clone(self),
+            }
+        }
+
+        fn resolve(&self, id: &str, value: T) -> bool {
+            match self.lock().remove(id) {
+                Some(sender) => sender.send(value).is_ok(),
+                None => false,
+            }
+        }
+
+        fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<String, oneshot::Sender<T>>> {
+            // The map is never left half-updated, so a poisoned lock is still usable.
+            self.senders.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+        }
+    }
+
+    /// A request's slot in a [`CallbackRegistry`], released when dropped.
+    struct PendingResult<T> {
+        id: String,
+        receiver: oneshot::Receiver<T>,
+        waiters: Arc<Waiters<T>>,
+    }
+
+    impl<T> PendingResult<T> {
+        async fn wait(mut self, timeout: Duration) -> Result<T, MpesaError> {
+            match tokio::time::timeout(timeout, &mut self.receiver).await {
+                Ok(Ok(value)) => Ok(value),
+                _ => Err(MpesaError::Timeout(self.id.clone())),
+            }
+        }
+    }
+
+    impl<T> Drop for PendingResult<T> {
+        fn drop(&mut self) {
+            self.waiters.lock().remove(&self.id);
+        }
+    }
+
+    /// Kind of request recorded in a [`TransactionStore`].
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+    pub enum TransactionKind {
+        StkPush,
+        B2c,
+    }
+
+    impl TransactionKind {
+        /// Name the kind is stored under, e.g. `"stk_push"`.
+        pub fn as_str(self) -> &'static str {
+            match self {
+                TransactionKind::StkPush => "stk_push",
+                TransactionKind::B2c => "b2c",
+            }
+        }
+    }
+
+    impl std::str::FromStr for TransactionKind {
+        type Err = MpesaError;
+
+        fn from_str(value: &str) -> Result<Self, Self::Err> {
+            match value {
+                "stk_push" => Ok(TransactionKind::StkPush),
+                "b2c" => Ok(TransactionKind::B2c),
+                other => Err(MpesaError::Store(
+                    format!("unknown transaction kind {:?}", other).into(),
+                )),
+            }
+        }
+    }
+
+    /// Where a recorded request stands.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+    pub enum TransactionStatus {
+        /// Accepted by Daraja, final result not known yet.
+        Pending,
+        /// The money moved.
+        Completed,
+        /// The request failed or was cancelled; no money moved.
+        Failed,
+        /// No final result could be found after the reconciler or recovery task
+        /// ran out of attempts; needs manual review.
+        Unresolved,
+    }
+
+    impl TransactionStatus {
+        /// Name the status is stored under, e.g. `"pending"`.
+        pub fn as_str(self) -> &'static str {
+            match self {
+                TransactionStatus::Pending => "pending",
+                TransactionStatus::Completed => "completed",
+                TransactionStatus::Failed => "failed",
+                TransactionStatus::Unresolved => "unresolved",
+            }
+        }
+    }
+
+    impl std::str::FromStr for TransactionStatus {
+        type Err = MpesaError;
+
+        fn from_str(value: &str) -> Result<Self, Self::Err> {
+            match value {
+                "pending" => Ok(TransactionStatus::Pending),
+                "completed" => Ok(TransactionStatus::Completed),
+                "failed" => Ok(TransactionStatus::Failed),
+                "unresolved" => Ok(TransactionStatus::Unresolved),
+                other => Err(MpesaError::Store(
+                    format!("unknown transaction status {:?}", other).into(),
+                )),
+            }
+        }
+    }
+
+    /// An STK Push or B2C payment as recorded in a [`TransactionStore`].
+    #[derive(Debug, Clone, PartialEq)]
+    pub struct TransactionRecord {
+        pub kind: TransactionKind,
+        /// `CheckoutRequestID` for STK Pushes, `OriginatorConversationID` for B2C.
+        pub request_id: String,
+        /// `MerchantRequestID` for STK Pushes, `ConversationID` for B2C.
+        pub conversation_id: Option<String>,
+        pub amount: u32,
+        /// The customer's phone number.
+        pub party: String,
+        pub short_code: String,
+        pub status: TransactionStatus,
+        pub result_code: Option<i32>,
+        pub result_desc: Option<String>,
+        /// M-Pesa receipt number of a completed transaction.
+        pub receipt: Option<String>,
+        pub created_at: DateTime<Utc>,
+        pub updated_at: DateTime<Utc>,
+    }
+
+    /// The final result of a recorded request, applied with
+    /// [`TransactionStore::update`].
+    #[derive(Debug, Clone, PartialEq)]
+    pub struct TransactionUpdate {
+        pub status: TransactionStatus,
+        pub result_code: Option<i32>,
+        pub result_desc: Option<String>,
+        pub receipt: Option<String>,
+    }
+
+    impl TransactionUpdate {
+        /// The update for an STK Push callback.
+        pub fn from_stk_callback(callback: &StkCallback) -> Self {
+            let receipt = callback
+                .callback_metadata
+                .as_ref()
+                .and_then(|metadata| metadata.get("MpesaReceiptNumber"))
+                .and_then(json_to_string);
+            TransactionUpdate {
+                status: if callback.result_code == 0 {
+                    TransactionStatus::Completed
+                } else {
+                    TransactionStatus::Failed
+                },
+                result_code: Some(callback.result_code),
+                result_desc: Some(callback.result_desc.clone()),
+                receipt,
+            }
+        }
+
+        /// The update for a B2C result.
+        pub fn from_b2c_result(result: &B2cResult) -> Self {
+            let completed = result.result_code == 0;
+            TransactionUpdate {
+                status: if completed {
+                    TransactionStatus::Completed
+                } else {
+                    TransactionStatus::Failed
+                },
+                result_code: Some(result.result_code),
+                result_desc: Some(result.result_desc.clone()),
+                receipt: completed.then(|| result.transaction_id.clone()),
+            }
+        }
+    }
+
+    /// Persists STK Pushes and B2C payments made by an [`MpesaClient`] so in-flight
+    /// transactions survive a restart.
+    ///
+    /// The client inserts a [`TransactionStatus::Pending`] record once Daraja
+    /// accepts a request and updates it from the callback passed to
+    /// [`MpesaClient::complete_stk_push`] or [`MpesaClient::complete_b2c_payment`].
+    /// Failures should be reported as [`MpesaError::Store`].
+    pub trait TransactionStore: Send + Sync + 'static {
+        /// Whether records are kept at all. Workers that read the pending list back,
+        /// such as [`StkReconciler`] and [`B2cRecovery`], refuse stores that set
+        /// this to `false`.
+        const PERSISTS: bool = true;
+
+        /// Records a new request, replacing any record with the same `request_id`.
+        fn insert(
+            &self,
+            record: TransactionRecord,
+        ) -> impl Future<Output = Result<(), MpesaError>> + Send;
+
+        /// Applies the final result of the request `request_id`. Returns `false` if
+        /// no such request is recorded.
+        fn update(
+            &self,
+            request_id: &str,
+            update: TransactionUpdate,
+        ) -> impl Future<Output = Result<bool, MpesaError>> + Send;
+
+        /// Like [`TransactionStore::update`], but only while the request is still
+        /// [`TransactionStatus::Pending`], so a result found by query never overwrites
+        /// one a callback delivered meanwhile. Returns `false` if nothing changed.
+        fn update_if_pending(
+            &self,
+            request_id: &str,
+            update: TransactionUpdate,
+        ) -> impl Future<Output = Result<bool, MpesaError>> + Send;
+
+        /// Returns the record of the request `request_id`.
+        fn get(
+            &self,
+            request_id: &str,
+        ) -> impl Future<Output = Result<Option<TransactionRecord>, MpesaError>> + Send;
+
+        /// Returns the pending requests of `kind`, oldest first.
+        fn pending(
+            &self,
+            kind: TransactionKind,
+        ) -> impl Future<Output = Result<Vec<TransactionRecord>, MpesaError>> + Send;
+    }
+
+    /// The default [`TransactionStore`], which records nothing.
+    #[derive(Debug, Clone, Copy, Default)]
+    pub struct NoStore;
+
+    impl TransactionStore for NoStore {
+        const PERSISTS: bool = false;
+
+        async fn insert(&self, _record: TransactionRecord) -> Result<(), MpesaError> {
+            Ok(())
+        }
+
+        async fn update(&self, _request_id: &str, _update: TransactionUpdate) -> Result<bool, MpesaError> {
+            Ok(false)
+        }
+
+        async fn update_if_pending(
+            &self,
+            _request_id: &str,
+            _update: TransactionUpdate,
+        ) -> Result<bool, MpesaError> {
+            Ok(false)
+        }
+
+        async fn get(&self, _request_id: &str) -> Result<Option<TransactionRecord>, MpesaError> {
+            Ok(None)
+        }
+
+        async fn pending(&self, _kind: TransactionKind) -> Result<Vec<TransactionRecord>, MpesaError> {
+            Ok(Vec::new())
+        }
+    }
+
+    /// A [`TransactionStore`] kept in memory, for tests and short-lived processes.
+    /// Clones share the same records.
+    #[derive(Debug, Clone, Default)]
+    pub struct MemoryStore {
+        records: Arc<std::sync::Mutex<HashMap<String, TransactionRecord>>>,
+    }
+
+    impl MemoryStore {
+        fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<String, TransactionRecord>> {
+            self.records.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+        }
+
+        fn apply(&self, request_id: &str, update: TransactionUpdate, pending_only: bool) -> bool {
+            let mut records = self.lock();
+            let Some(record) = records.get_mut(request_id) else {
+                return false;
+            };
+            if pending_only && record.status != TransactionStatus::Pending {
+                return false;
+            }
+            record.status = update.status;
+            record.result_code = update.result_code;
+            record.result_desc = update.result_desc;
+            record.receipt = update.receipt;
+            record.updated_at = Utc::now();
+            true
+        }
+    }
+
+    impl TransactionStore for MemoryStore {
+        async fn insert(&self, record: TransactionRecord) -> Result<(), MpesaError> {
+            self.lock().insert(record.request_id.clone(), record);
+            Ok(())
+        }
+
+        async fn update(&self, request_id: &str, update: TransactionUpdate) -> Result<bool, MpesaError> {
+            Ok(self.apply(request_id, update, false))
+        }
+
+        async fn update_if_pending(
+            &self,
+            request_id: &str,
+            update: TransactionUpdate,
+        ) -> Result<bool, MpesaError> {
+            Ok(self.apply(request_id, update, true))
+        }
+
+        async fn get(&self, request_id: &str) -> Result<Option<TransactionRecord>, MpesaError> {
+            Ok(self.lock().get(request_id).cloned())
+        }
+
+        async fn pending(&self, kind: TransactionKind) -> Result<Vec<TransactionRecord>, MpesaError> {
+            let mut pending: Vec<_> = self
+                .lock()
+                .values()
+                .filter(|record| record.kind == kind && record.status == TransactionStatus::Pending)
+                .cloned()
+                .collect();
+            pending.sort_by_key(|record| record.created_at);
+            Ok(pending)
+        }
+    }
+
+    /// Business details shared by most requests, set once on the client with
+    /// [`MpesaClientBuilder::profile`].
+    ///
+    /// Request builders that leave the initiator, security credential, shortcode,
+    /// passkey or a callback URL unset use the profile's value instead.
+    ///
+    /// # Examples
+    /// ```
+    /// use mpesa_daraja::mpesa::{BusinessProfile, Environment, MpesaClient};
+    /// let client = MpesaClient::builder("consumer_key", "consumer_secret")
+    ///     .environment(Environment::Sandbox)
+    ///     .profile(
+    ///         BusinessProfile::default()
+    ///             .short_code("174379")
+    ///             .passkey("passkey")
+    ///             .callback_url("https://example.com/stk/callback"),
+    ///     )
+    ///     .build();
+    /// ```
+    #[derive(Clone, Default)]
+    pub struct BusinessProfile {
+        short_code: Option<String>,
+        passkey: Option<String>,
+        initiator_name: Option<String>,
+        security_credential: Option<String>,
+        callback_url: Option<String>,
+        result_url: Option<String>,
+        queue_timeout_url: Option<String>,
+    }
+
+    impl fmt::Debug for BusinessProfile {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            f.debug_struct("BusinessProfile")
+                .field("short_code", &self.short_code)
+                .field("passkey", &redact(&self.passkey))
+                .field("initiator_name", &self.initiator_name)
+                .field("security_credential", &redact(&self.security_credential))
+                .field("callback_url", &self.callback_url)
+                .field("result_url", &self.result_url)
+                .field("queue_timeout_url", &self.queue_timeout_url)
+                .finish()
+        }
+    }
+
+    impl BusinessProfile {
+        /// The business shortcode.
+        pub fn short_code(mut self, short_code: impl Into<String>) -> Self {
+            self.short_code = Some(short_code.into());
+            self
+        }
+
+        /// The STK Push passkey from Safaricom.
+        pub fn passkey(mut self, passkey: impl Into<String>) -> Self {
+            self.passkey = Some(passkey.into());
+            self
+        }
+
+        /// The initiator username.
+        pub fn initiator_name(mut self, initiator_name: impl Into<String>) -> Self {
+            self.initiator_name = Some(initiator_name.into());
+            self
+        }
+
+        /// Generated security credential.
+        pub fn security_credential(mut self, security_credential: impl Into<String>) -> Self {
+            self.security_credential = Some(security_credential.into());
+            self
+        }
+
+        /// URL to receive STK Push results.
+        pub fn callback_url(mut self, callback_url: impl Into<String>) -> Self {
+            self.callback_url = Some(callback_url.into());
+            self
+        }
+
+        /// URL to receive B2C, B2B, balance, status and reversal results.
+        pub fn result_url(mut self, result_url: impl Into<String>) -> Self {
+            self.result_url = Some(result_url.into());
+            self
+        }
+
+        /// URL for timeout notifications.
+        pub fn queue_timeout_url(mut self, queue_timeout_url: impl Into<String>) -> Self {
+            self.queue_timeout_url = Some(queue_timeout_url.into());
+            self
+        }
+    }
+
+    /// Client configuration loaded by [`MpesaClient::from_env`] or
+    /// [`MpesaClient::from_config`].
+    ///
+    /// Only the consumer key and secret are required. The security credential is
+    /// either given directly or generated from the initiator password and
+    /// certificate, see [`MpesaClient::generate_security_credential`].
+    #[derive(Deserialize, Clone, Default)]
+    #[serde(default, deny_unknown_fields)]
+    pub struct MpesaConfig {
+        pub consumer_key: Option<String>,
+        pub consumer_secret: Option<String>,
+        /// `"sandbox"` (default), `"production"` or a custom base URL.
+        pub environment: Option<String>,
+        pub short_code: Option<String>,
+        pub passkey: Option<String>,
+        pub initiator_name: Option<String>,
+        pub initiator_password: Option<String>,
+        pub security_credential: Option<String>,
+        pub cert_path: Option<String>,
+        pub callback_url: Option<String>,
+        pub result_url: Option<String>,
+        pub queue_timeout_url: Option<String>,
+    }
+
+    impl fmt::Debug for MpesaConfig {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            f.debug_struct("MpesaConfig")
+                .field("consumer_key", &self.consumer_key)
+                .field("consumer_secret", &redact(&self.consumer_secret))
+                .field("environment", &self.environment)
+                .field("short_code", &self.short_code)
+                .field("passkey", &redact(&self.passkey))
+                .field("initiator_name", &self.initiator_name)
+                .field("initiator_password", &redact(&self.initiator_password))
+                .field("security_credential", &redact(&self.security_credential))
+                .field("cert_path", &self.cert_path)
+                .field("callback_url", &self.callback_url)
+                .field("result_url", &self.result_url)
+                .field("queue_timeout_url", &self.queue_timeout_url)
+                .finish()
+        }
+    }
+
+    /// Prefix of the environment variables read by [`MpesaClient::from_env`].
+    const ENV_PREFIX: &str = "MPESA_";
+
+    impl MpesaConfig {
+        fn from_env() -> Self {
+            let var = |name: &str| {
+                std::env::var(format!("{}{}", ENV_PREFIX, name.to_uppercase()))
+                    .ok()
+                    .filter(|value| !value.trim().is_empty())
+            };
+            MpesaConfig {
+                consumer_key: var("consumer_key"),
+                consumer_secret: var("consumer_secret"),
+                environment: var("environment"),
+                short_code: var("short_code"),
+                passkey: var("passkey"),
+                initiator_name: var("initiator_name"),
+                initiator_password: var("initiator_password"),
+                security_credential: var("security_credential"),
+                cert_path: var("cert_path"),
+                callback_url: var("callback_url"),
+                result_url: var("result_url"),
+                queue_timeout_url: var("queue_timeout_url"),
+            }
+        }
+
+        fn from_file(path: &Path) -> Result<Self, MpesaError> {
+            let contents = fs::read_to_string(path).map_err(|err| {
+                MpesaError::Config(format!("cannot read {}: {}", path.display(), err))
+            })?;
+            let invalid = |err: &dyn fmt::Display| {
+                MpesaError::Config(format!("invalid {}: {}", path.display(), err))
+            };
+            match path.extension().and_then(|ext| ext.to_str()) {
+                Some("toml") => toml::from_str(&contents).map_err(|err| invalid(&err)),
+                Some("json") => serde_json::from_str(&contents).map_err(|err| invalid(&err)),
+                _ => Err(MpesaError::Config(format!(
+                    "{} must have a .toml or .json extension",
+                    path.display()
+                ))),
+            }
+        }
+
+        /// Builds a client, naming every missing required setting with `name`.
+        fn into_client(self, name: impl Fn(&str) -> String) -> Result<MpesaClient, MpesaError> {
+            let missing: Vec<String> = [
+                ("consumer_key", self.consumer_key.is_none()),
+                ("consumer_secret", self.consumer_secret.is_none()),
+            ]
+            .iter()
+            .filter(|(_, missing)| *missing)
+            .map(|(field, _)| name(field))
+            .collect();
+            if !missing.is_empty() {
+                return Err(MpesaError::Config(format!("missing {}", missing.join(", "))));
+            }
+
+            let environment = match self.environment {
+                Some(environment) => environment.parse()?,
+                None => Environment::Sandbox,
+            };
+            let security_credential = match (self.security_credential, self.initiator_password) {
+                (Some(credential), _) => Some(credential),
+                (None, Some(password)) => Some(MpesaClient::generate_security_credential(
+                    &password,
+                    environment == Environment::Production,
+                    self.cert_path.as_deref(),
+                )?),
+                (None, None) => None,
+            };
+
+            let profile = BusinessProfile {
+                short_code: self.short_code,
+                passkey: self.passkey,
+                initiator_name: self.initiator_name,
+                security_credential,
+                callback_url: self.callback_url,
+                result_url: self.result_url,
+                queue_timeout_url: self.queue_timeout_url,
+            };
+
+            Ok(MpesaClient::builder(
+                &self.consumer_key.unwrap_or_default(),
+                &self.consumer_secret.unwrap_or_default(),
+            )
+            .environment(environment)
+            .profile(profile)
+            .build())
+        }
+    }
+
+    /// Resolves request fields against the client's [`BusinessProfile`], collecting
+    /// the names of fields set on neither.
+    struct ProfileFallback<'a> {
+        profile: &'a BusinessProfile,
+        missing: Vec<&'static str>,
+    }
+
+    impl<'a> ProfileFallback<'a> {
+        fn new(profile: &'a BusinessProfile) -> Self {
+            ProfileFallback {
+                profile,
+                missing: Vec::new(),
+            }
+        }
+
+        fn resolve(
+            &mut self,
+            name: &'static str,
+            value: &Option<String>,
+            fallback: fn(&BusinessProfile) -> &Option<String>,
+        ) -> String {
+            match value.as_ref().or(fallback(self.profile).as_ref()) {
+                Some(value) => value.clone(),
+                None => {
+                    self.missing.push(name);
+                    String::new()
+                }
+            }
+        }
+
+        /// Fails with [`MpesaError::InvalidRequest`] if any resolved field was missing.
+        fn finish(self) -> Result<(), MpesaError> {
+            if self.missing.is_empty() {
+                Ok(())
+            } else {
+                Err(MpesaError::InvalidRequest(format!(
+                    "missing required fields: {}",
+                    self.missing.join(", ")
+                )))
+            }
+        }
+    }
+
+    /// Initiator, shortcode and result URLs shared by the B2C, B2B, balance, status
+    /// and reversal requests, resolved against the client's profile.
+    struct InitiatorFields {
+        initiator_name: String,
+        security_credential: String,
+        short_code: String,
+        result_url: String,
+        queue_timeout_url: String,
+    }
+
+    #[derive(Deserialize)]
+    struct AccessTokenResponse {
+        access_token: String,
+        expires_in: String,
+    }
+
+    /// How long before its expiry a cached access token is refreshed.
+    const TOKEN_REFRESH_MARGIN: Duration = Duration::from_secs(60);
+
+    // Access token cache
+    struct CachedToken {
+        access_token: String,
+        expires_at: Instant,
+    }
+
+    impl CachedToken {
+        fn is_fresh(&self) -> bool {
+            Instant::now() + TOKEN_REFRESH_MARGIN < self.expires_at
+        }
+    }
+
+    /// An STK Push (C2B) prompt, built with [`StkPushRequest::builder`].
+    #[derive(Debug, Clone)]
+    pub struct StkPushRequest {
+        phone_number: String,
+        amount: u32,
+        account_reference: String,
+        transaction_desc: String,
+        callback_url: Option<String>,
+        short_code: Option<String>,
+        passkey: Option<String>,
+    }
+
+    /// Builder for [`StkPushRequest`]. An unset shortcode, passkey and callback URL fall back
+    /// to the client's [`BusinessProfile`]; every other field is required.
+    #[derive(Debug, Clone, Default)]
+    pub struct StkPushRequestBuilder {
+        phone_number: Option<String>,
+        amount: Option<u32>,
+        account_reference: Option<String>,
+        transaction_desc: Option<String>,
+        callback_url: Option<String>,
+        short_code: Option<String>,
+        passkey: Option<String>,
+    }
+
+    impl StkPushRequest {
+        /// Starts building an STK Push.
+        pub fn builder() -> StkPushRequestBuilder {
+            StkPushRequestBuilder::default()
+        }
+    }
+
+    impl StkPushRequestBuilder {
+        /// The customer's phone number (e.g., "2547XXXXXXXX").
+        pub fn phone_number(mut self, phone_number: impl Into<String>) -> Self {
+            self.phone_number = Some(phone_number.into());
+            self
+        }
+
+        /// The amount to charge in KES.
+        pub fn amount(mut self, amount: u32) -> Self {
+            self.amount = Some(amount);
+            self
+        }
+
+        /// A reference for the transaction (e.g., invoice number).
+        pub fn account_reference(mut self, account_reference: impl Into<String>) -> Self {
+            self.account_reference = Some(account_reference.into());
+            self
+        }
+
+        /// A description of the transaction.
+        pub fn transaction_desc(mut self, transaction_desc: impl Into<String>) -> Self {
+            self.transaction_desc = Some(transaction_desc.into());
+            self
+        }
+
+        /// URL to receive the transaction result.
+        pub fn callback_url(mut self, callback_url: impl Into<String>) -> Self {
+            self.callback_url = Some(callback_url.into());
+            self
+        }
+
+        /// The business shortcode.
+        pub fn short_code(mut self, short_code: impl Into<String>) -> Self {
+            self.short_code = Some(short_code.into());
+            self
+        }
+
+        /// The passkey from Safaricom.
+        pub fn passkey(mut self, passkey: impl Into<String>) -> Self {
+            self.passkey = Some(passkey.into());
+            self
+        }
+
+        /// Validates the builder, returning [`MpesaError::InvalidRequest`] listing
+        /// every missing field.
+        pub fn build(self) -> Result<StkPushRequest, MpesaError> {
+            check_required(&[
+                ("phone_number", self.phone_number.is_some()),
+                ("amount", self.amount.is_some()),
+                ("account_reference", self.account_reference.is_some()),
+                ("transaction_desc", self.transaction_desc.is_some()),
+            ])?;
+
+            Ok(StkPushRequest {
+                phone_number: self.phone_number.unwrap_or_default(),
+                amount: self.amount.unwrap_or_default(),
+                account_reference: self.account_reference.unwrap_or_default(),
+                transaction_desc: self.transaction_desc.unwrap_or_default(),
+                callback_url: self.callback_url,
+                short_code: self.short_code,
+                passkey: self.passkey,
+            })
+        }
+    }
+
+    #[derive(Serialize)]
+    struct StkPushPayload {
+        #[serde(rename = "BusinessShortCode")]
+        business_short_code: String,
+        #[serde(rename = "Password")]
+        password: String,
+        #[serde(rename = "Timestamp")]
+        timestamp: String,
+        #[serde(rename = "TransactionType")]
+        transaction_type: String,
+        #[serde(rename = "Amount")]
+        amount: String,
+        #[serde(rename = "PartyA")]
+        party_a: String,
+        #[serde(rename = "PartyB")]
+        party_b: String,
+        #[serde(rename = "PhoneNumber")]
+        phone_number: String,
+        #[serde(rename = "CallBackURL")]
+        callback_url: String,
+        #[serde(rename = "AccountReference")]
+        account_reference: String,
+        #[serde(rename = "TransactionDesc")]
+        transaction_desc: String,
+    }
+
+    /// Response from an STK Push (C2B) request.
+    #[derive(Deserialize)]
+    pub struct StkPushResponse {
+        #[serde(rename = "MerchantRequestID")]
+        pub merchant_request_id: Option<String>,
+        #[serde(rename = "CheckoutRequestID")]
+        pub checkout_request_id: Option<String>,
+        #[serde(rename = "ResponseCode")]
+        pub response_code: String,
+        #[serde(rename = "ResponseDescription")]
+        pub response_description: String,
+        #[serde(rename = "CustomerMessage")]
+        pub customer_message: Option<String>,
+        #[serde(skip)]
+        pending: Option<PendingResult<StkCallback>>,
+    }
+
+    impl StkPushResponse {
+        /// Waits up to `timeout` for the callback of this STK Push.
+        ///
+        /// Resolves once the callback is passed to [`CallbackRegistry::resolve_stk`];
+        /// returns [`MpesaError::Timeout`] if it does not arrive in time.
+        ///
+        /// # Examples
+        /// ```no_run
+        /// # use std::time::Duration;
+        /// # use mpesa_daraja::mpesa::{MpesaClient, MpesaError, StkOutcome};
+        /// # async fn run(client: MpesaClient) -> Result<(), MpesaError> {
+        /// let callback = client
+        ///     .stk_push("254712345678", 10, "INV-1", "Invoice", "https://example.com/cb", "174379", "passkey")
+        ///     .await?
+        ///     .wait_for_result(Duration::from_secs(60))
+        ///     .await?;
+        /// if let StkOutcome::Paid(payment) = callback.outcome()? {
+        ///     println!("Paid: {}", payment.mpesa_receipt_number);
+        /// }
+        /// # Ok(())
+        /// # }
+        /// ```
+        pub async fn wait_for_result(mut self, timeout: Duration) -> Result<StkCallback, MpesaError> {
+            match self.pending.take() {
+                Some(pending) => pending.wait(timeout).await,
+                None => Err(MpesaError::InvalidRequest(
+                    "response has no CheckoutRequestID to wait on".to_string(),
+                )),
+            }
+        }
+    }
+
+    // STK Push Callback
+    /// Payload Daraja posts to the STK Push `CallBackURL`.
+    #[derive(Serialize, Deserialize)]
+    pub struct StkCallbackRequest {
+        #[serde(rename = "Body")]
+        pub body: StkCallbackBody,
+    }
+
+    /// Body of an STK Push callback.
+    #[derive(Serialize, Deserialize)]
+    pub struct StkCallbackBody {
+        #[serde(rename = "stkCallback")]
+        pub stk_callback: StkCallback,
+    }
+
+    /// Final result of an STK Push transaction.
+    #[derive(Serialize, Deserialize, Clone)]
+    pub struct StkCallback {
+        #[serde(rename = "MerchantRequestID")]
+        pub merchant_request_id: String,
+        #[serde(rename = "CheckoutRequestID")]
+        pub checkout_request_id: String,
+        #[serde(rename = "ResultCode", deserialize_with = "code_from_string_or_number")]
+        pub result_code: i32,
+        #[serde(rename = "ResultDesc")]
+        pub result_desc: String,
+        #[serde(rename = "CallbackMetadata")]
+        pub callback_metadata: Option<CallbackMetadata>,
+        /// Set on callbacks an [`StkReconciler`] built from an STK Push Query rather
+        /// than received from Daraja. These never carry `CallbackMetadata`.
+        #[serde(skip)]
+        pub reconciled: bool,
+    }
+
+    /// Metadata sent with a successful STK Push callback.
+    #[derive(Serialize, Deserialize, Clone)]
+    pub struct CallbackMetadata {
+        #[serde(rename = "Item")]
+        pub item: Vec<CallbackItem>,
+    }
+
+    /// Name-value pair in STK Push callback metadata; `value` may be a string, a
+    /// number or missing altogether.
+    #[derive(Serialize, Deserialize, Clone)]
+    pub struct CallbackItem {
+        #[serde(rename = "Name")]
+        pub name: String,
+        #[serde(rename = "Value")]
+        pub value: Option<serde_json::Value>,
+    }
+
+    /// Details of a paid STK Push, extracted from its callback metadata.
+    #[derive(Debug, Clone)]
+    pub struct StkPayment {
+        pub merchant_request_id: String,
+        pub checkout_request_id: String,
+        pub amount: f64,
+        pub mpesa_receipt_number: String,
+        /// Transaction time as reported by Daraja (East Africa Time).
+        pub transaction_date: NaiveDateTime,
+        pub phone_number: String,
+    }
+
+    /// Reason an STK Push was not paid, derived from its `ResultCode`.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum StkFailureReason {
+        /// `1`: the customer's balance is insufficient.
+        InsufficientFunds,
+        /// `1001`: another transaction is already in progress for the customer.
+        SubscriberLocked,
+        /// `1019`: the transaction expired before it was processed.
+        Expired,
+        /// `1032`: the customer cancelled the prompt.
+        Cancelled,
+        /// `1037`: the customer could not be reached or did not respond in time.
+        Timeout,
+        /// `2001`: the customer entered a wrong PIN.
+        InvalidPin,
+        /// Any other non-zero `ResultCode`.
+        Other(i32),
+    }
+
+    impl From<i32> for StkFailureReason {
+        fn from(code: i32) -> Self {
+            match code {
+                1 => StkFailureReason::InsufficientFunds,
+                1001 => StkFailureReason::SubscriberLocked,
+                1019 => StkFailureReason::Expired,
+                1032 => StkFailureReason::Cancelled,
+                1037 => StkFailureReason::Timeout,
+                2001 => StkFailureReason::InvalidPin,
+                other => StkFailureReason::Other(other),
+            }
+        }
+    }
+
+    /// An STK Push that was not paid.
+    #[derive(Debug, Clone)]
+    pub struct StkFailure {
+        pub merchant_request_id: String,
+        pub checkout_request_id: String,
+        pub reason: StkFailureReason,
+        pub result_code: i32,
+        pub result_desc: String,
+    }
+
+    /// A paid STK Push whose callback never arrived, as confirmed by an
+    /// [`StkReconciler`]. The query result carries no receipt number, amount or
+    /// transaction date.
+    #[derive(Debug, Clone)]
+    pub struct StkConfirmation {
+        pub merchant_request_id: String,
+        pub checkout_request_id: String,
+        pub result_desc: String,
+    }
+
+    /// Typed outcome of an STK Push callback.
+    #[derive(Debug, Clone)]
+    pub enum StkOutcome {
+        Paid(StkPayment),
+        /// Paid, according to a reconciled callback.
+        Confirmed(StkConfirmation),
+        Failed(StkFailure),
+    }
+
+    impl CallbackMetadata {
+        /// Returns the value of the item called `name`, if present.
+        pub fn get(&self, name: &str) -> Option<&serde_json::Value> {
+            self.item
+                .iter()
+                .find(|item| item.name == name)
+                .and_then(|item| item.value.as_ref())
+        }
+    }
+
+    impl StkCallback {
+        /// Interprets the callback as a payment or a typed failure. A successful
+        /// [`reconciled`](StkCallback::reconciled) callback is a
+        /// [`StkOutcome::Confirmed`] payment.
+        ///
+        /// Returns [`MpesaError::InvalidCallback`] if a successful callback from Daraja
+        /// is missing any of `Amount`, `MpesaReceiptNumber`, `TransactionDate` or
+        /// `PhoneNumber`.
+        ///
+        /// # Examples
+        /// ```
+        /// use mpesa_daraja::mpesa::{StkCallbackRequest, StkOutcome};
+        /// let payload = r#"{"Body":{"stkCallback":{"MerchantRequestID":"29115-34620561-1",
+        ///     "CheckoutRequestID":"ws_CO_191220191020363925","ResultCode":0,
+        ///     "ResultDesc":"The service request is processed successfully.",
+        ///     "CallbackMetadata":{"Item":[{"Name":"Amount","Value":1.00},
+        ///     {"Name":"MpesaReceiptNumber","Value":"NLJ7RT61SV"},{"Name":"Balance"},
+        ///     {"Name":"TransactionDate","Value":20191219102115},
+        ///     {"Name":"PhoneNumber","Value":254708374149}]}}}}"#;
+        /// let callback: StkCallbackRequest = serde_json::from_str(payload).unwrap();
+        /// match callback.body.stk_callback.outcome().unwrap() {
+        ///     StkOutcome::Paid(payment) => assert_eq!(payment.mpesa_receipt_number, "NLJ7RT61SV"),
+        ///     other => panic!("unexpected outcome: {:?}", other),
+        /// }
+        /// ```
+        pub fn outcome(&self) -> Result<StkOutcome, MpesaError> {
+            if self.result_code != 0 {
+                return Ok(StkOutcome::Failed(StkFailure {
+                    merchant_request_id: self.merchant_request_id.clone(),
+                    checkout_request_id: self.checkout_request_id.clone(),
+                    reason: StkFailureReason::from(self.result_code),
+                    result_code: self.result_code,
+                    result_desc: self.result_desc.clone(),
+                }));
+            }
+            if self.reconciled {
+                return Ok(StkOutcome::Confirmed(StkConfirmation {
+                    merchant_request_id: self.merchant_request_id.clone(),
+                    checkout_request_id: self.checkout_request_id.clone(),
+                    result_desc: self.result_desc.clone(),
+                }));
+            }
+
+            let metadata = self.callback_metadata.as_ref().ok_or_else(|| {
+                MpesaError::InvalidCallback("missing CallbackMetadata".to_string())
+            })?;
+            let field = |name: &str| {
+                metadata
+                    .get(name)
+                    .and_then(json_to_string)
+                    .ok_or_else(|| MpesaError::InvalidCallback(format!("missing {}", name)))
+            };
+
+            let amount = field("Amount")?;
+            let amount = amount.parse().map_err(|_| {
+                MpesaError::InvalidCallback(format!("invalid Amount {}", amount))
+            })?;
+            let transaction_date = field("TransactionDate")?;
+            let transaction_date = NaiveDateTime::parse_from_str(&transaction_date, "%Y%m%d%H%M%S")
+                .map_err(|_| {
+                    MpesaError::InvalidCallback(format!("invalid TransactionDate {}", transaction_date))
+                })?;
+
+            Ok(StkOutcome::Paid(StkPayment {
+                merchant_request_id: self.merchant_request_id.clone(),
+                checkout_request_id: self.checkout_request_id.clone(),
+                amount,
+                mpesa_receipt_number: field("MpesaReceiptNumber")?,
+                transaction_date,
+                phone_number: field("PhoneNumber")?,
+            }))
+        }
+    }
+
+    /// Renders a string or number JSON value as text.
+    fn json_to_string(value: &serde_json::Value) -> Option<String> {
+        match value {
+            serde_json::Value::String(s) => Some(s.clone()),
+            serde_json::Value::Number(n) => Some(n.to_string()),
+            _ => None,
+        }
+    }
+
+    // STK Push Query
+    #[derive(Serialize)]
+    struct StkPushQueryPayload {
+        #[serde(rename = "BusinessShortCode")]
+        business_short_code: String,
+        #[serde(rename = "Password")]
+        password: String,
+        #[serde(rename = "Timestamp")]
+        timestamp: String,
+        #[serde(rename = "CheckoutRequestID")]
+        checkout_request_id: String,
+    }
+
+    /// Response from an STK Push Query request.
+    #[derive(Deserialize)]
+    pub struct StkPushQueryResponse {
+        #[serde(rename = "MerchantRequestID")]
+        pub merchant_request_id: Option<String>,
+        #[serde(rename = "CheckoutRequestID")]
+        pub checkout_request_id: Option<String>,
+        #[serde(rename = "ResponseCode")]
+        pub response_code: String,
+        #[serde(rename = "ResponseDescription")]
+        pub response_description: String,
+        #[serde(rename = "ResultCode")]
+        pub result_code: String,
+        #[serde(rename = "ResultDesc")]
+        pub result_desc: String,
+    }
+
+    impl StkPushQueryResponse {
+        /// Whether the customer completed the payment (`ResultCode` `"0"`).
+        pub fn is_paid(&self) -> bool {
+            self.result_code == "0"
+        }
+    }
+
+    // C2B Register URL
+    /// What M-Pesa does with a C2B payment when the validation URL cannot be reached.
+    #[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum C2bResponseType {
+        Completed,
+        Cancelled,
+    }
+
+    /// Version of the C2B API; v2 masks the customer's MSISDN in callbacks.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum C2bVersion {
+        V1,
+        V2,
+    }
+
+    #[derive(Serialize)]
+    struct C2bRegisterUrlPayload {
+        #[serde(rename = "ShortCode")]
+        short_code: String,
+        #[serde(rename = "ResponseType")]
+        response_type: C2bResponseType,
+        #[serde(rename = "ConfirmationURL")]
+        confirmation_url: String,
+        #[serde(rename = "ValidationURL")]
+        validation_url: String,
+    }
+
+    /// Response from a C2B Register URL request.
+    #[derive(Deserialize)]
+    pub struct C2bRegisterUrlResponse {
+        // Daraja misspells this field in its response.
+        #[serde(rename = "OriginatorCoversationID", alias = "OriginatorConversationID")]
+        pub originator_conversation_id: Option<String>,
+        #[serde(rename = "ResponseCode")]
+        pub response_code: String,
+        #[serde(rename = "ResponseDescription")]
+        pub response_description: String,
+    }
+
+    // C2B Simulate
+    /// Type of payment simulated by [`MpesaClient::simulate_c2b`].
+    #[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum C2bCommand {
+        CustomerPayBillOnline,
+        CustomerBuyGoodsOnline,
+    }
+
+    #[derive(Serialize)]
+    struct C2bSimulatePayload {
+        #[serde(rename = "ShortCode")]
+        short_code: String,
+        #[serde(rename = "CommandID")]
+        command_id: C2bCommand,
+        #[serde(rename = "Amount")]
+        amount: String,
+        #[serde(rename = "Msisdn")]
+        msisdn: String,
+        #[serde(rename = "BillRefNumber")]
+        bill_ref_number: Option<String>,
+    }
+
+    /// Response from a C2B simulate request.
+    #[derive(Deserialize)]
+    pub struct C2bSimulateResponse {
+        #[serde(rename = "OriginatorCoversationID", alias = "OriginatorConversationID")]
+        pub originator_conversation_id: Option<String>,
+        #[serde(rename = "ResponseCode")]
+        pub response_code: String,
+        #[serde(rename = "ResponseDescription")]
+        pub response_description: String,
+    }
+
+    // C2B Validation and Confirmation Callbacks
+    /// Payload Daraja posts to the C2B validation and confirmation URLs.
+    #[derive(Serialize, Deserialize, Debug, Clone)]
+    pub struct C2bPayment {
+        #[serde(rename = "TransactionType")]
+        pub transaction_type: String,
+        #[serde(rename = "TransID")]
+        pub trans_id: String,
+        #[serde(rename = "TransTime")]
+        pub trans_time: String,
+        #[serde(rename = "TransAmount")]
+        pub trans_amount: String,
+        #[serde(rename = "BusinessShortCode")]
+        pub business_short_code: String,
+        #[serde(rename = "BillRefNumber", default)]
+        pub bill_ref_number: String,
+        #[serde(rename = "InvoiceNumber", default)]
+        pub invoice_number: String,
+        #[serde(rename = "OrgAccountBalance", default)]
+        pub org_account_balance: String,
+        #[serde(rename = "ThirdPartyTransID", default)]
+        pub third_party_trans_id: String,
+        #[serde(rename = "MSISDN")]
+        pub msisdn: String,
+        #[serde(rename = "FirstName", default)]
+        pub first_name: String,
+        #[serde(rename = "MiddleName", default)]
+        pub middle_name: String,
+        #[serde(rename = "LastName", default)]
+        pub last_name: String,
+    }
+
+    impl C2bPayment {
+        /// `TransAmount` as a number.
+        pub fn amount(&self) -> Option<f64> {
+            self.trans_amount.trim().parse().ok()
+        }
+
+        /// `TransTime` as reported by Daraja (East Africa Time).
+        pub fn transaction_time(&self) -> Option<NaiveDateTime> {
+            NaiveDateTime::parse_from_str(&self.trans_time, "%Y%m%d%H%M%S").ok()
+        }
+    }
+
+    /// Reason for rejecting a C2B payment at validation.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum C2bRejection {
+        InvalidMsisdn,
+        InvalidAccountNumber,
+        InvalidAmount,
+        InvalidKycDetails,
+        InvalidShortcode,
+        Other,
+    }
+
+    impl C2bRejection {
+        /// The `ResultCode` Daraja expects for this rejection.
+        pub fn result_code(&self) -> &'static str {
+            match self {
+                C2bRejection::InvalidMsisdn => "C2B00011",
+                C2bRejection::InvalidAccountNumber => "C2B00012",
+                C2bRejection::InvalidAmount => "C2B00013",
+                C2bRejection::InvalidKycDetails => "C2B00014",
+                C2bRejection::InvalidShortcode => "C2B00015",
+                C2bRejection::Other => "C2B00016",
+            }
+        }
+
+        fn result_desc(&self) -> &'static str {
+            match self {
+                C2bRejection::InvalidMsisdn => "Rejected: Invalid MSISDN",
+                C2bRejection::InvalidAccountNumber => "Rejected: Invalid Account Number",
+                C2bRejection::InvalidAmount => "Rejected: Invalid Amount",
+                C2bRejection::InvalidKycDetails => "Rejected: Invalid KYC Details",
+                C2bRejection::InvalidShortcode => "Rejected: Invalid Shortcode",
+                C2bRejection::Other => "Rejected: Other Error",
+            }
+        }
+    }
+
+    /// Reply to a C2B validation request. [`C2bValidationResponse::accept`] is also
+    /// the expected acknowledgement of a confirmation.
+    #[derive(Serialize, Deserialize, Debug, Clone)]
+    pub struct C2bValidationResponse {
+        #[serde(rename = "ResultCode")]
+        pub result_code: String,
+        #[serde(rename = "ResultDesc")]
+        pub result_desc: String,
+    }
+
+    impl C2bValidationResponse {
+        /// Accepts the payment.
+        pub fn accept() -> Self {
+            C2bValidationResponse {
+                result_code: "0".to_string(),
+                result_desc: "Accepted".to_string(),
+            }
+        }
+
+        /// Rejects the payment with the given reason.
+        pub fn reject(reason: C2bRejection) -> Self {
+            C2bValidationResponse {
+                result_code: reason.result_code().to_string(),
+                result_desc: reason.result_desc().to_string(),
+            }
+        }
+    }
+
+    /// Error response from any MPESA API call.
+    #[derive(Deserialize, Debug)]
+    pub struct ErrorResponse {
+        #[serde(rename = "responseId", alias = "requestId")]
+        pub response_id: Option<String>,        // Support both field names with Option
+    
+        #[serde(rename = "errorCode")]
+        pub error_code: String,
+    
+        #[serde(rename = "errorMessage")]
+        pub error_message: String,
+    }
+
+    /// Errors returned by [`MpesaClient`].
+    #[derive(Debug)]
+    pub enum MpesaError {
+        /// The [`Transport`] could not send the request or read its response.
+        Transport(Box<dyn Error + Send + Sync>),
+        /// Daraja refused to issue an access token, or rejected a freshly issued one.
+        Auth { status: u16, body: String },
+        /// Daraja answered with an unexpected HTTP status and no recognisable error body.
+        Status { status: u16, body: String },
+        /// Daraja returned an error body, e.g. `errorCode` `"400.002.02"`.
+        Api(ErrorResponse),
+        /// The request was acknowledged with a non-zero `ResponseCode`.
+        Rejected {
+            response_code: String,
+            response_description: String,
+        },
+        /// The response body could not be decoded; `body` holds the raw text.
+        Decode {
+            body: String,
+            source: serde_json::Error,
+        },
+        /// The request was refused locally and never sent to Daraja.
+        InvalidRequest(String),
+        /// The client configuration is incomplete or could not be loaded.
+        Config(String),
+        /// The certificate used for the security credential could not be read.
+        Io(std::io::Error),
+        /// The security credential could not be encrypted with the certificate.
+        Crypto(ErrorStack),
+        /// A callback payload is missing a field needed to interpret it.
+        InvalidCallback(String),
+        /// No callback arrived in time for the request with this ID.
+        Timeout(String),
+        /// A [`TransactionStore`] failed to read or write a record.
+        Store(Box<dyn Error + Send + Sync>),
+    }
+
+    impl MpesaError {
+        /// The Daraja `errorCode`, if this is an [`MpesaError::Api`] error.
+        pub fn error_code(&self) -> Option<&str> {
+            match self {
+                MpesaError::Api(err) => Some(&err.error_code),
+                _ => None,
+            }
+        }
+    }
+
+    impl fmt::Display for MpesaError {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            match self {
+                MpesaError::Transport(err) => write!(f, "Transport error: {}", err),
+                MpesaError::Auth { status, body } => {
+                    write!(f, "Authentication failed ({}): {}", status, body)
+                }
+                MpesaError::Status { status, body } => {
+                    write!(f, "Unexpected HTTP status {}: {}", status, body)
+                }
+                MpesaError::Api(err) => write!(
+                    f,
+                    "M-Pesa Error {}: {} (Request ID: {})",
+                    err.error_code,
+                    err.error_message,
+                    err.response_id.as_deref().unwrap_or_default()
+                ),
+                MpesaError::Rejected {
+                    response_code,
+                    response_description,
+                } => write!(
+                    f,
+                    "M-Pesa rejected the request {}: {}",
+                    response_code, response_description
+                ),
+                MpesaError::Decode { body, source } => {
+                    write!(f, "Unknown M-Pesa response ({}): {}", source, body)
+                }
+                MpesaError::InvalidRequest(msg) => write!(f, "Invalid request: {}", msg),
+                MpesaError::Config(msg) => write!(f, "Configuration error: {}", msg),
+                MpesaError::Io(err) => write!(f, "Certificate error: {}", err),
+                MpesaError::Crypto(err) => write!(f, "Encryption error: {}", err),
+                MpesaError::InvalidCallback(msg) => write!(f, "Invalid callback: {}", msg),
+                MpesaError::Timeout(id) => write!(f, "Timed out waiting for the callback of {}", id),
+                MpesaError::Store(err) => write!(f, "Store error: {}", err),
+            }
+        }
+    }
+
+    impl Error for MpesaError {
+        fn source(&self) -> Option<&(dyn Error + 'static)> {
+            match self {
+                MpesaError::Transport(err) => Some(err.as_ref()),
+                MpesaError::Store(err) => Some(err.as_ref()),
+                MpesaError::Decode { source, .. } => Some(source),
+                MpesaError::Io(err) => Some(err),
+                MpesaError::Crypto(err) => Some(err),
+                _ => None,
+            }
+        }
+    }
+
+    impl From<std::io::Error> for MpesaError {
+        fn from(err: std::io::Error) -> Self {
+            MpesaError::Io(err)
+        }
+    }
+
+    impl From<ErrorStack> for MpesaError {
+        fn from(err: ErrorStack) -> Self {
+            MpesaError::Crypto(err)
+        }
+    }
+
+    /// Synchronous acknowledgements that carry a Daraja `ResponseCode`.
+    trait Acknowledgement {
+        fn response_code(&self) -> &str;
+        fn response_description(&self) -> &str;
+    }
+
+    macro_rules! impl_acknowledgement {
+        ($($ty:ty),+ $(,)?) => {
+            $(impl Acknowledgement for $ty {
+                fn response_code(&self) -> &str {
+                    &self.response_code
+                }
+
+                fn response_description(&self) -> &str {
+                    &self.response_description
+                }
+            })+
+        };
+    }
+
+    /// Decodes a Daraja response body, mapping error bodies, unexpected statuses
+    /// and non-zero `ResponseCode`s to the matching [`MpesaError`].
+    fn parse_response<R>(status: StatusCode, text: String) -> Result<R, MpesaError>
+    where
+        R: DeserializeOwned + Acknowledgement,
+    {
+        if let Ok(err) = serde_json::from_str::<ErrorResponse>(&text) {
+            return Err(MpesaError::Api(err));
+        }
+
+        if !status.is_success() {
+            return Err(MpesaError::Status {
+                status: status.as_u16(),
+                body: text,
+            });
+        }
+
+        let response: R = match serde_json::from_str(&text) {
+            Ok(response) => response,
+            Err(source) => return Err(MpesaError::Decode { body: text, source }),
+        };
+
+        if response.response_code() != "0" {
+            return Err(MpesaError::Rejected {
+                response_code: response.response_code().to_string(),
+                response_description: response.response_description().to_string(),
+            });
+        }
+
+        Ok(response)
+    }
+
+    // B2C
+    /// Type of B2C payment; each has its own charges and customer messaging.
+    #[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+    pub enum B2cCommand {
+        #[default]
+        BusinessPayment,
+        SalaryPayment,
+        PromotionPayment,
+    }
+
+    /// A B2C payment to a customer's phone number, built with [`B2cRequest::builder`].
+    #[derive(Debug, Clone)]
+    pub struct B2cRequest {
+        command: B2cCommand,
+        phone_number: String,
+        amount: u32,
+        remarks: String,
+        result_url: Option<String>,
+        queue_timeout_url: Option<String>,
+        initiator_name: Option<String>,
+        security_credential: Option<String>,
+        short_code: Option<String>,
+        occasion: String,
+    }
+
+    /// Builder for [`B2cRequest`]. The command defaults to `BusinessPayment` and the
+    /// occasion to empty. Unset business fields fall back to the client's
+    /// [`BusinessProfile`]; every other field is required.
+    #[derive(Debug, Clone, Default)]
+    pub struct B2cRequestBuilder {
+        command: B2cCommand,
+        phone_number: Option<String>,
+        amount: Option<u32>,
+        remarks: Option<String>,
+        result_url: Option<String>,
+        queue_timeout_url: Option<String>,
+        initiator_name: Option<String>,
+        security_credential: Option<String>,
+        short_code: Option<String>,
+        occasion: Option<String>,
+    }
+
+    impl B2cRequest {
+        /// Starts building a B2C payment.
+        pub fn builder() -> B2cRequestBuilder {
+            B2cRequestBuilder::default()
+        }
+    }
+
+    impl B2cRequestBuilder {
+        /// The type of payment; defaults to `BusinessPayment`.
+        pub fn command(mut self, command: B2cCommand) -> Self {
+            self.command = command;
+            self
+        }
+
+        /// Recipient's phone number (e.g., "2547XXXXXXXX").
+        pub fn phone_number(mut self, phone_number: impl Into<String>) -> Self {
+            self.phone_number = Some(phone_number.into());
+            self
+        }
+
+        /// Amount to send in KES.
+        pub fn amount(mut self, amount: u32) -> Self {
+            self.amount = Some(amount);
+            self
+        }
+
+        /// Transaction remarks.
+        pub fn remarks(mut self, remarks: impl Into<String>) -> Self {
+            self.remarks = Some(remarks.into());
+            self
+        }
+
+        /// URL to receive the result callback.
+        pub fn result_url(mut self, result_url: impl Into<String>) -> Self {
+            self.result_url = Some(result_url.into());
+            self
+        }
+
+        /// URL for timeout notifications.
+        pub fn queue_timeout_url(mut self, queue_timeout_url: impl Into<String>) -> Self {
+            self.queue_timeout_url = Some(queue_timeout_url.into());
+            self
+        }
+
+        /// The initiator username.
+        pub fn initiator_name(mut self, initiator_name: impl Into<String>) -> Self {
+            self.initiator_name = Some(initiator_name.into());
+            self
+        }
+
+        /// Generated security credential.
+        pub fn security_credential(mut self, security_credential: impl Into<String>) -> Self {
+            self.security_credential = Some(security_credential.into());
+            self
+        }
+
+        /// Business shortcode sending the payment.
+        pub fn short_code(mut self, short_code: impl Into<String>) -> Self {
+            self.short_code = Some(short_code.into());
+            self
+        }
+
+        /// Optional occasion description.
+        pub fn occasion(mut self, occasion: impl Into<String>) -> Self {
+            self.occasion = Some(occasion.into());
+            self
+        }
+
+        /// Validates the builder, returning [`MpesaError::InvalidRequest`] listing
+        /// every missing field.
+        pub fn build(self) -> Result<B2cRequest, MpesaError> {
+            check_required(&[
+                ("phone_number", self.phone_number.is_some()),
+                ("amount", self.amount.is_some()),
+                ("remarks", self.remarks.is_some()),
+            ])?;
+
+            Ok(B2cRequest {
+                command: self.command,
+                phone_number: self.phone_number.unwrap_or_default(),
+                amount: self.amount.unwrap_or_default(),
+                remarks: self.remarks.unwrap_or_default(),
+                result_url: self.result_url,
+                queue_timeout_url: self.queue_timeout_url,
+                initiator_name: self.initiator_name,
+                security_credential: self.security_credential,
+                short_code: self.short_code,
+                occasion: self.occasion.unwrap_or_default(),
+            })
+        }
+    }
+
+    /// Fails with [`MpesaError::InvalidRequest`] naming every field whose flag is `false`.
+    fn check_required(fields: &[(&str, bool)]) -> Result<(), MpesaError> {
+        let missing: Vec<&str> = fields
+            .iter()
+            .filter(|(_, present)| !present)
+            .map(|(name, _)| *name)
+            .collect();
+        if missing.is_empty() {
+            Ok(())
+        } else {
+            Err(MpesaError::InvalidRequest(format!(
+                "missing required fields: {}",
+                missing.join(", ")
+            )))
+        }
+    }
+
+    #[derive(Serialize)]
+    struct B2cPayload {
+        #[serde(rename = "InitiatorName")]
+        initiator_name: String,
+        #[serde(rename = "SecurityCredential")]
+        security_credential: String,
+        #[serde(rename = "CommandID")]
+        command_id: B2cCommand,
+        #[serde(rename = "Amount")]
+        amount: String,
+        #[serde(rename = "PartyA")]
+        party_a: String,
+        #[serde(rename = "PartyB")]
+        party_b: String,
+        #[serde(rename = "Remarks")]
+        remarks: String,
+        #[serde(rename = "QueueTimeOutURL")]
+        queue_timeout_url: String,
+        #[serde(rename = "ResultURL")]
+        result_url: String,
+        #[serde(rename = "Occasion")]
+        occasion: String,
+    }
+
+    /// Response from a B2C payment request.
+    #[derive(Deserialize)]
+    pub struct B2cResponse {
+        #[serde(rename = "ConversationID")]
+        pub conversation_id: Option<String>,
+        #[serde(rename = "OriginatorConversationID")]
+        pub originator_conversation_id: Option<String>,
+        #[serde(rename = "ResponseCode")]
+        pub response_code: String,
+        #[serde(rename = "ResponseDescription")]
+        pub response_description: String,
+        #[serde(skip)]
+        pending: Option<PendingResult<B2cResult>>,
+    }
+
+    impl B2cResponse {
+        /// Waits up to `timeout` for the result of this payment.
+        ///
+        /// Resolves once the result is passed to [`CallbackRegistry::resolve_b2c`];
+        /// returns [`MpesaError::Timeout`] if it does not arrive in time.
+        pub async fn wait_for_result(mut self, timeout: Duration) -> Result<B2cResult, MpesaError> {
+            match self.pending.take() {
+                Some(pending) => pending.wait(timeout).await,
+                None => Err(MpesaError::InvalidRequest(
+                    "response has no ConversationID to wait on".to_string(),
+                )),
+            }
+        }
+    }
+
+    /// Payload Daraja posts to the B2C `ResultURL`.
+    #[derive(Serialize, Deserialize)]
+    pub struct B2cResultCallback {
+        #[serde(rename = "Result")]
+        pub result: B2cResult,
+    }
+
+    /// Final result of a B2C payment.
+    #[derive(Serialize, Deserialize, Clone)]
+    pub struct B2cResult {
+        #[serde(rename = "ResultType")]
+        pub result_type: i32,
+        #[serde(rename = "ResultCode", deserialize_with = "code_from_string_or_number")]
+        pub result_code: i32,
+        #[serde(rename = "ResultDesc")]
+        pub result_desc: String,
+        #[serde(rename = "OriginatorConversationID")]
+        pub originator_conversation_id: String,
+        #[serde(rename = "ConversationID")]
+        pub conversation_id: String,
+        #[serde(rename = "TransactionID")]
+        pub transaction_id: String,
+        /// Present on successful payments only.
+        #[serde(rename = "ResultParameters")]
+        pub result_parameters: Option<ResultParameters>,
+    }
+
+    /// Details of a completed B2C payment, extracted from its result parameters.
+    #[derive(Debug, Clone)]
+    pub struct B2cPayment {
+        pub originator_conversation_id: String,
+        pub conversation_id: String,
+        pub transaction_amount: f64,
+        pub transaction_receipt: String,
+        /// Phone number part of `ReceiverPartyPublicName`.
+        pub receiver_phone_number: String,
+        /// Name part of `ReceiverPartyPublicName`; empty if Daraja sent none.
+        pub receiver_name: String,
+        pub recipient_is_registered_customer: Option<bool>,
+        /// Completion time as reported by Daraja (East Africa Time).
+        pub transaction_completed_at: NaiveDateTime,
+        pub utility_account_available_funds: Option<f64>,
+        pub working_account_available_funds: Option<f64>,
+        pub charges_paid_account_available_funds: Option<f64>,
+    }
+
+    /// A B2C payment that did not complete.
+    #[derive(Debug, Clone)]
+    pub struct B2cFailure {
+        pub originator_conversation_id: String,
+        pub conversation_id: String,
+        pub result_code: i32,
+        pub result_desc: String,
+    }
+
+    /// Typed outcome of a B2C result callback.
+    #[derive(Debug, Clone)]
+    pub enum B2cOutcome {
+        Paid(B2cPayment),
+        Failed(B2cFailure),
+    }
+
+    impl B2cResult {
+        /// Interprets the result as a completed payment or a failure.
+        ///
+        /// Returns [`MpesaError::InvalidCallback`] if a successful result is missing
+        /// `TransactionAmount`, `TransactionReceipt`, `ReceiverPartyPublicName` or
+        /// `TransactionCompletedDateTime`, or one of them cannot be parsed.
+        ///
+        /// # Examples
+        /// ```
+        /// use mpesa_daraja::mpesa::{B2cOutcome, B2cResultCallback};
+        /// let payload = r#"{"Result":{"ResultType":0,"ResultCode":0,
+        ///     "ResultDesc":"The service request is processed successfully.",
+        ///     "OriginatorConversationID":"10571-7910404-1",
+        ///     "ConversationID":"AG_20191219_00004e48cf7e3533f581","TransactionID":"NLJ41HAY6Q",
+        ///     "ResultParameters":{"ResultParameter":[{"Key":"TransactionAmount","Value":10},
+        ///     {"Key":"TransactionReceipt","Value":"NLJ41HAY6Q"},
+        ///     {"Key":"B2CRecipientIsRegisteredCustomer","Value":"Y"},
+        ///     {"Key":"B2CChargesPaidAccountAvailableFunds","Value":-4510.00},
+        ///     {"Key":"ReceiverPartyPublicName","Value":"254708374149 - John Doe"},
+        ///     {"Key":"TransactionCompletedDateTime","Value":"19.12.2019 11:45:50"},
+        ///     {"Key":"B2CUtilityAccountAvailableFunds","Value":10116.00},
+        ///     {"Key":"B2CWorkingAccountAvailableFunds","Value":900000.00}]}}}"#;
+        /// let callback: B2cResultCallback = serde_json::from_str(payload).unwrap();
+        /// match callback.result.outcome().unwrap() {
+        ///     B2cOutcome::Paid(payment) => {
+        ///         assert_eq!(payment.transaction_amount, 10.0);
+        ///         assert_eq!(payment.receiver_name, "John Doe");
+        ///     }
+        ///     B2cOutcome::Failed(failure) => panic!("unexpected failure: {:?}", failure),
+        /// }
+        /// ```
+        pub fn outcome(&self) -> Result<B2cOutcome, MpesaError> {
+            if self.result_code != 0 {
+                return Ok(B2cOutcome::Failed(B2cFailure {
+                    originator_conversation_id: self.originator_conversation_id.clone(),
+                    conversation_id: self.conversation_id.clone(),
+                    result_code: self.result_code,
+                    result_desc: self.result_desc.clone(),
+                }));
+            }
+
+            let parameters = self.result_parameters.as_ref().ok_or_else(|| {
+                MpesaError::InvalidCallback("missing ResultParameters".to_string())
+            })?;
+            // "2547XXXXXXXX - JOHN DOE"
+            let public_name = parameters.required("ReceiverPartyPublicName")?.to_string();
+            let (receiver_phone_number, receiver_name) =
+                public_name.split_once(" - ").unwrap_or((&public_name, ""));
+
+            Ok(B2cOutcome::Paid(B2cPayment {
+                originator_conversation_id: self.originator_conversation_id.clone(),
+                conversation_id: self.conversation_id.clone(),
+                transaction_amount: parameters.required_decimal("TransactionAmount")?,
+                transaction_receipt: parameters.required("TransactionReceipt")?.to_string(),
+                receiver_phone_number: receiver_phone_number.trim().to_string(),
+                receiver_name: receiver_name.trim().to_string(),
+                recipient_is_registered_customer: parameters
+                    .get("B2CRecipientIsRegisteredCustomer")
+                    .map(|value| value.as_str() == Some("Y")),
+                transaction_completed_at: parameters
+                    .required_datetime("TransactionCompletedDateTime")?,
+                utility_account_available_funds: parameters
+                    .decimal("B2CUtilityAccountAvailableFunds")?,
+                working_account_available_funds: parameters
+                    .decimal("B2CWorkingAccountAvailableFunds")?,
+                charges_paid_account_available_funds: parameters
+                    .decimal("B2CChargesPaidAccountAvailableFunds")?,
+            }))
+        }
+    }
+
+    // B2B
+    /// Type of business-to-business payment.
+    #[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+    pub enum B2bCommand {
+        /// Pay another business's paybill, with an account reference.
+        #[default]
+        BusinessPayBill,
+        /// Pay another business's till.
+        BusinessBuyGoods,
+    }
+
+    impl B2bCommand {
+        /// The kind of party a payment of this type is received by: a shortcode for
+        /// `BusinessPayBill`, a till number for `BusinessBuyGoods`.
+        pub fn receiver_identifier_type(&self) -> IdentifierType {
+            match self {
+                B2bCommand::BusinessPayBill => IdentifierType::Shortcode,
+                B2bCommand::BusinessBuyGoods => IdentifierType::TillNumber,
+            }
+        }
+    }
+
+    /// A B2B payment to a paybill or till, built with [`B2bRequest::builder`].
+    #[derive(Debug, Clone)]
+    pub struct B2bRequest {
+        command: B2bCommand,
+        amount: u32,
+        receiver_short_code: String,
+        sender_identifier_type: IdentifierType,
+        receiver_identifier_type: IdentifierType,
+        account_reference: String,
+        requester: Option<String>,
+        remarks: String,
+        queue_timeout_url: Option<String>,
+        result_url: Option<String>,
+        initiator_name: Option<String>,
+        security_credential: Option<String>,
+        short_code: Option<String>,
+    }
+
+    /// Builder for [`B2bRequest`]. The requester is optional and unset business fields
+    /// fall back to the client's [`BusinessProfile`]; every other field is required.
+    #[derive(Debug, Clone, Default)]
+    pub struct B2bRequestBuilder {
+        command: Option<B2bCommand>,
+        amount: Option<u32>,
+        receiver_short_code: Option<String>,
+        sender_identifier_type: IdentifierType,
+        receiver_identifier_type: Option<IdentifierType>,
+        account_reference: Option<String>,
+        requester: Option<String>,
+        remarks: Option<String>,
+        queue_timeout_url: Option<String>,
+        result_url: Option<String>,
+        initiator_name: Option<String>,
+        security_credential: Option<String>,
+        short_code: Option<String>,
+    }
+
+    impl B2bRequest {
+        /// Starts building a B2B payment.
+        pub fn builder() -> B2bRequestBuilder {
+            B2bRequestBuilder::default()
+        }
+    }
+
+    impl B2bRequestBuilder {
+        /// `BusinessPayBill` or `BusinessBuyGoods`.
+        pub fn command(mut self, command: B2bCommand) -> Self {
+            self.command = Some(command);
+            self
+        }
+
+        /// Amount to send in KES.
+        pub fn amount(mut self, amount: u32) -> Self {
+            self.amount = Some(amount);
+            self
+        }
+
+        /// The paybill or till receiving the payment.
+        pub fn receiver_short_code(mut self, receiver_short_code: impl Into<String>) -> Self {
+            self.receiver_short_code = Some(receiver_short_code.into());
+            self
+        }
+
+        /// What kind of party the sending shortcode is; defaults to a shortcode.
+        pub fn sender_identifier_type(mut self, identifier_type: IdentifierType) -> Self {
+            self.sender_identifier_type = identifier_type;
+            self
+        }
+
+        /// What kind of party the receiver is; defaults to
+        /// [`B2bCommand::receiver_identifier_type`] of the command.
+        pub fn receiver_identifier_type(mut self, identifier_type: IdentifierType) -> Self {
+            self.receiver_identifier_type = Some(identifier_type);
+            self
+        }
+
+        /// The account number at the receiving paybill.
+        pub fn account_reference(mut self, account_reference: impl Into<String>) -> Self {
+            self.account_reference = Some(account_reference.into());
+            self
+        }
+
+        /// Phone number of the customer the payment is made on behalf of.
+        pub fn requester(mut self, requester: impl Into<String>) -> Self {
+            self.requester = Some(requester.into());
+            self
+        }
+
+        /// Transaction remarks.
+        pub fn remarks(mut self, remarks: impl Into<String>) -> Self {
+            self.remarks = Some(remarks.into());
+            self
+        }
+
+        /// URL for timeout notifications.
+        pub fn queue_timeout_url(mut self, queue_timeout_url: impl Into<String>) -> Self {
+            self.queue_timeout_url = Some(queue_timeout_url.into());
+            self
+        }
+
+        /// URL to receive the result callback.
+        pub fn result_url(mut self, result_url: impl Into<String>) -> Self {
+            self.result_url = Some(result_url.into());
+            self
+        }
+
+        /// The initiator username.
+        pub fn initiator_name(mut self, initiator_name: impl Into<String>) -> Self {
+            self.initiator_name = Some(initiator_name.into());
+            self
+        }
+
+        /// Generated security credential.
+        pub fn security_credential(mut self, security_credential: impl Into<String>) -> Self {
+            self.security_credential = Some(security_credential.into());
+            self
+        }
+
+        /// Business shortcode sending the payment.
+        pub fn short_code(mut self, short_code: impl Into<String>) -> Self {
+            self.short_code = Some(short_code.into());
+            self
+        }
+
+        /// Validates the builder, returning [`MpesaError::InvalidRequest`] listing
+        /// every missing field.
+        pub fn build(self) -> Result<B2bRequest, MpesaError> {
+            check_required(&[
+                ("command", self.command.is_some()),
+                ("amount", self.amount.is_some()),
+                ("receiver_short_code", self.receiver_short_code.is_some()),
+                ("account_reference", self.account_reference.is_some()),
+                ("remarks", self.remarks.is_some()),
+            ])?;
+
+            let command = self.command.unwrap_or_default();
+            Ok(B2bRequest {
+                command,
+                amount: self.amount.unwrap_or_default(),
+                receiver_short_code: self.receiver_short_code.unwrap_or_default(),
+                sender_identifier_type: self.sender_identifier_type,
+                receiver_identifier_type: self
+                    .receiver_identifier_type
+                    .unwrap_or_else(|| command.receiver_identifier_type()),
+                account_reference: self.account_reference.unwrap_or_default(),
+                requester: self.requester,
+                remarks: self.remarks.unwrap_or_default(),
+                queue_timeout_url: self.queue_timeout_url,
+                result_url: self.result_url,
+                initiator_name: self.initiator_name,
+                security_credential: self.security_credential,
+                short_code: self.short_code,
+            })
+        }
+    }
+
+    #[derive(Serialize)]
+    struct B2bPayload {
+        #[serde(rename = "Initiator")]
+        initiator: String,
+        #[serde(rename = "SecurityCredential")]
+        security_credential: String,
+        #[serde(rename = "CommandID")]
+        command_id: B2bCommand,
+        #[serde(rename = "SenderIdentifierType")]
+        sender_identifier_type: String,
+        // Daraja's spelling.
+        #[serde(rename = "RecieverIdentifierType")]
+        receiver_identifier_type: String,
+        #[serde(rename = "Amount")]
+        amount: String,
+        #[serde(rename = "PartyA")]
+        party_a: String,
+        #[serde(rename = "PartyB")]
+        party_b: String,
+        #[serde(rename = "AccountReference")]
+        account_reference: String,
+        #[serde(rename = "Requester", skip_serializing_if = "Option::is_none")]
+        requester: Option<String>,
+        #[serde(rename = "Remarks")]
+        remarks: String,
+        #[serde(rename = "QueueTimeOutURL")]
+        queue_timeout_url: String,
+        #[serde(rename = "ResultURL")]
+        result_url: String,
+    }
+
+    /// Response from a B2B payment request.
+    #[derive(Deserialize)]
+    pub struct B2bResponse {
+        #[serde(rename = "ConversationID")]
+        pub conversation_id: Option<String>,
+        #[serde(rename = "OriginatorConversationID")]
+        pub originator_conversation_id: Option<String>,
+        #[serde(rename = "ResponseCode")]
+        pub response_code: String,
+        #[serde(rename = "ResponseDescription")]
+        pub response_description: String,
+    }
+
+    /// Type of party identified by `PartyA` in balance and transaction status queries,
+    /// and by either party of a B2B payment.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+    pub enum IdentifierType {
+        /// A customer phone number.
+        Msisdn,
+        /// A buy goods till number.
+        TillNumber,
+        /// An organisation shortcode (paybill).
+        #[default]
+        Shortcode,
+    }
+
+    impl IdentifierType {
+        /// The `IdentifierType` code Daraja expects.
+        pub fn code(&self) -> &'static str {
+            match self {
+                IdentifierType::Msisdn => "1",
+                IdentifierType::TillNumber => "2",
+                IdentifierType::Shortcode => "4",
+            }
+        }
+    }
+
+    /// An account balance query, built with [`BalanceRequest::builder`].
+    #[derive(Debug, Clone)]
+    pub struct BalanceRequest {
+        initiator_name: Option<String>,
+        security_credential: Option<String>,
+        short_code: Option<String>,
+        identifier_type: IdentifierType,
+        remarks: String,
+        queue_timeout_url: Option<String>,
+        result_url: Option<String>,
+    }
+
+    /// Builder for [`BalanceRequest`]. The identifier type defaults to
+    /// [`IdentifierType::Shortcode`] and unset business fields fall back to the client's
+    /// [`BusinessProfile`]; the remarks are required.
+    #[derive(Debug, Clone, Default)]
+    pub struct BalanceRequestBuilder {
+        initiator_name: Option<String>,
+        security_credential: Option<String>,
+        short_code: Option<String>,
+        identifier_type: IdentifierType,
+        remarks: Option<String>,
+        queue_timeout_url: Option<String>,
+        result_url: Option<String>,
+    }
+
+    impl BalanceRequest {
+        /// Starts building a balance query.
+        pub fn builder() -> BalanceRequestBuilder {
+            BalanceRequestBuilder::default()
+        }
+    }
+
+    impl BalanceRequestBuilder {
+        /// The initiator username.
+        pub fn initiator_name(mut self, initiator_name: impl Into<String>) -> Self {
+            self.initiator_name = Some(initiator_name.into());
+            self
+        }
+
+        /// Generated security credential.
+        pub fn security_credential(mut self, security_credential: impl Into<String>) -> Self {
+            self.security_credential = Some(security_credential.into());
+            self
+        }
+
+        /// Business shortcode, till number or MSISDN being queried.
+        pub fn short_code(mut self, short_code: impl Into<String>) -> Self {
+            self.short_code = Some(short_code.into());
+            self
+        }
+
+        /// What kind of party the short code is; defaults to a shortcode.
+        pub fn identifier_type(mut self, identifier_type: IdentifierType) -> Self {
+            self.identifier_type = identifier_type;
+            self
+        }
+
+        /// Request remarks.
+        pub fn remarks(mut self, remarks: impl Into<String>) -> Self {
+            self.remarks = Some(remarks.into());
+            self
+        }
+
+        /// URL for timeout notifications.
+        pub fn queue_timeout_url(mut self, queue_timeout_url: impl Into<String>) -> Self {
+            self.queue_timeout_url = Some(queue_timeout_url.into());
+            self
+        }
+
+        /// URL to receive the result callback.
+        pub fn result_url(mut self, result_url: impl Into<String>) -> Self {
+            self.result_url = Some(result_url.into());
+            self
+        }
+
+        /// Validates the builder, returning [`MpesaError::InvalidRequest`] listing
+        /// every missing field.
+        pub fn build(self) -> Result<BalanceRequest, MpesaError> {
+            check_required(&[
+                ("remarks", self.remarks.is_some()),
+            ])?;
+
+            Ok(BalanceRequest {
+                initiator_name: self.initiator_name,
+                security_credential: self.security_credential,
+                short_code: self.short_code,
+                identifier_type: self.identifier_type,
+                remarks: self.remarks.unwrap_or_default(),
+                queue_timeout_url: self.queue_timeout_url,
+                result_url: self.result_url,
+            })
+        }
+    }
+
+    #[derive(Serialize)]
+    struct BalancePayload {
+        #[serde(rename = "Initiator")]
+        initiator: String,
+        #[serde(rename = "SecurityCredential")]
+        security_credential: String,
+        #[serde(rename = "CommandID")]
+        command_id: String,
+        #[serde(rename = "PartyA")]
+        party_a: String,
+        #[serde(rename = "IdentifierType")]
+        identifier_type: String,
+        #[serde(rename = "Remarks")]
+        remarks: String,
+        #[serde(rename = "QueueTimeOutURL")]
+        queue_timeout_url: String,
+        #[serde(rename = "ResultURL")]
+        result_url: String,
+    }
+
+    /// Immediate response from a balance query.
+    #[derive(Serialize, Deserialize)]
+    pub struct BalanceQueryResponse {
+        #[serde(rename = "ConversationID")]
+        pub conversation_id: Option<String>,
+        #[serde(rename = "OriginatorConversationID")]
+        pub originator_conversation_id: Option<String>,
+        #[serde(rename = "ResponseCode")]
+        pub response_code: String,
+        #[serde(rename = "ResponseDescription")]
+        pub response_description: String,
+    }
+
+    /// Payload Daraja posts to the balance query `ResultURL`.
+    #[derive(Serialize, Deserialize)]
+    pub struct BalanceResultCallback {
+        #[serde(rename = "Result")]
+        pub result: BalanceResponse,
+    }
+
+    /// Callback response for balance queries. Transaction status callbacks are
+    /// modelled by [`TransactionStatusResult`].
+    // Shared Callback Structs (for Balance and Transaction Status)
+    #[derive(Serialize, Deserialize)]
+    pub struct BalanceResponse { // shared
+        #[serde(rename = "ResultType")]
+        pub result_type: i32,
+        #[serde(rename = "ResultCode", deserialize_with = "code_from_string_or_number")]
+        pub result_code: i32,
+        #[serde(rename = "ResultDesc")]
+        pub result_desc: String,
+        #[serde(rename = "OriginatorConversationID")]
+        pub originator_conversation_id: String,
+        #[serde(rename = "ConversationID")]
+        pub conversation_id: String,
+        #[serde(rename = "TransactionID")]
+        pub transaction_id: String,
+        /// Empty when the query failed.
+        #[serde(rename = "ResultParameters", default)]
+        pub result_parameters: ResultParameters,
+    }
+
+    /// Balance of one account under a shortcode.
+    #[derive(Debug, Clone, PartialEq)]
+    pub struct AccountBalance {
+        pub name: String,
+        pub currency: String,
+        pub current_balance: f64,
+        pub available_balance: f64,
+        pub reserved_amount: f64,
+        pub uncleared_balance: f64,
+    }
+
+    /// Account balances reported by a balance query callback.
+    #[derive(Debug, Clone)]
+    pub struct AccountBalances {
+        pub accounts: Vec<AccountBalance>,
+        /// `BOCompletedTime` as reported by Daraja (East Africa Time).
+        pub completed_at: Option<NaiveDateTime>,
+    }
+
+    impl AccountBalances {
+        /// Returns the account called `name`, e.g. `"Utility Account"`.
+        pub fn get(&self, name: &str) -> Option<&AccountBalance> {
+            self.accounts.iter().find(|account| account.name == name)
+        }
+    }
+
+    /// Parses an `AccountBalance` result value of the form
+    /// `"Working Account|KES|1000.00|1000.00|0.00|0.00&Utility Account|KES|..."`.
+    ///
+    /// # Examples
+    /// ```
+    /// use mpesa_daraja::mpesa::parse_account_balances;
+    /// let accounts = parse_account_balances(
+    ///     "Working Account|KES|700000.00|700000.00|0.00|0.00&Utility Account|KES|228037.00|228037.00|0.00|0.00",
+    /// ).unwrap();
+    /// assert_eq!(accounts[1].name, "Utility Account");
+    /// assert_eq!(accounts[1].available_balance, 228037.0);
+    /// ```
+    pub fn parse_account_balances(value: &str) -> Result<Vec<AccountBalance>, MpesaError> {
+        value
+            .split('&')
+            .filter(|account| !account.trim().is_empty())
+            .map(|account| {
+                let invalid = || {
+                    MpesaError::InvalidCallback(format!("invalid AccountBalance {}", account))
+                };
+                let fields: Vec<&str> = account.split('|').map(str::trim).collect();
+                let [name, currency, current, available, reserved, uncleared] = fields[..] else {
+                    return Err(invalid());
+                };
+                let amount = |field: &str| field.parse::<f64>().map_err(|_| invalid());
+                Ok(AccountBalance {
+                    name: name.to_string(),
+                    currency: currency.to_string(),
+                    current_balance: amount(current)?,
+                    available_balance: amount(available)?,
+                    reserved_amount: amount(reserved)?,
+                    uncleared_balance: amount(uncleared)?,
+                })
+            })
+            .collect()
+    }
+
+    impl BalanceResponse {
+        /// Parses the `AccountBalance` and `BOCompletedTime` result parameters.
+        ///
+        /// Returns [`MpesaError::InvalidCallback`] if `AccountBalance` is missing or malformed.
+        pub fn account_balances(&self) -> Result<AccountBalances, MpesaError> {
+            let value = self.result_parameters.required("AccountBalance")?.to_string();
+            Ok(AccountBalances {
+                accounts: parse_account_balances(&value)?,
+                completed_at: self
+                    .result_parameters
+                    .get("BOCompletedTime")
+                    .and_then(ResultValue::as_datetime),
+            })
+        }
+    }
+
+    /// Parameters in a callback response.
+    #[derive(Serialize, Deserialize, Default, Clone)]
+    pub struct ResultParameters { // shared
+        // Daraja sends a single object when there is only one parameter.
+        #[serde(rename = "ResultParameter", deserialize_with = "one_or_many")]
+        pub result_parameter: Vec<ResultParameter>,
+    }
+
+    impl ResultParameters {
+        /// Returns the value of the parameter called `key`, or `None` if the
+        /// parameter is absent or was sent without a value.
+        ///
+        /// # Examples
+        /// ```
+        /// use mpesa_daraja::mpesa::ResultParameters;
+        /// let parameters: ResultParameters = serde_json::from_str(
+        ///     r#"{"ResultParameter":[{"Key":"TransactionAmount","Value":100},
+        ///     {"Key":"TransactionReceipt","Value":"NLJ41HAY6Q"},{"Key":"Charge"}]}"#,
+        /// ).unwrap();
+        /// assert_eq!(parameters.get("TransactionAmount").and_then(|v| v.as_i64()), Some(100));
+        /// assert_eq!(parameters.get("TransactionReceipt").and_then(|v| v.as_str()), Some("NLJ41HAY6Q"));
+        /// assert!(parameters.get("Charge").is_none());
+        /// ```
+        pub fn get(&self, key: &str) -> Option<&ResultValue> {
+            self.result_parameter
+                .iter()
+                .find(|parameter| parameter.key == key)
+                .map(|parameter| &parameter.value)
+                .filter(|value| !value.is_missing())
+        }
+
+        fn required(&self, key: &str) -> Result<&ResultValue, MpesaError> {
+            self.get(key)
+                .ok_or_else(|| MpesaError::InvalidCallback(format!("missing {}", key)))
+        }
+
+        fn required_decimal(&self, key: &str) -> Result<f64, MpesaError> {
+            let value = self.required(key)?;
+            value.as_decimal().ok_or_else(|| invalid_parameter(key, value))
+        }
+
+        fn required_datetime(&self, key: &str) -> Result<NaiveDateTime, MpesaError> {
+            let value = self.required(key)?;
+            value.as_datetime().ok_or_else(|| invalid_parameter(key, value))
+        }
+
+        /// Like `get(key).and_then(ResultValue::as_decimal)`, but a value that is
+        /// present and not a number is an error rather than `None`.
+        fn decimal(&self, key: &str) -> Result<Option<f64>, MpesaError> {
+            self.get(key)
+                .map(|value| value.as_decimal().ok_or_else(|| invalid_parameter(key, value)))
+                .transpose()
+        }
+    }
+
+    fn invalid_parameter(key: &str, value: &ResultValue) -> MpesaError {
+        MpesaError::InvalidCallback(format!("invalid {} {}", key, value))
+    }
+
+    /// Key-value pair in a callback response.
+    #[derive(Serialize, Deserialize, Clone)]
+    pub struct ResultParameter { // shared
+        #[serde(rename = "Key")]
+        pub key: String,
+        #[serde(rename = "Value", default)]
+        pub value: ResultValue,
+    }
+
+    /// Value of a [`ResultParameter`]. Daraja sends strings, integers or floats,
+    /// and sometimes omits the value altogether.
+    #[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+    #[serde(untagged)]
+    pub enum ResultValue {
+        String(String),
+        Integer(i64),
+        Float(f64),
+        #[default]
+        Missing,
+    }
+
+    impl ResultValue {
+        /// Whether the parameter was sent without a value.
+        pub fn is_missing(&self) -> bool {
+            matches!(self, ResultValue::Missing)
+        }
+
+        /// The value if it was sent as a string.
+        pub fn as_str(&self) -> Option<&str> {
+            match self {
+                ResultValue::String(s) => Some(s),
+                _ => None,
+            }
+        }
+
+        /// The value as a number, parsing strings such as `"1000.00"`.
+        pub fn as_decimal(&self) -> Option<f64> {
+            match self {
+                ResultValue::String(s) => s.trim().parse().ok(),
+                ResultValue::Integer(n) => Some(*n as f64),
+                ResultValue::Float(n) => Some(*n),
+                ResultValue::Missing => None,
+            }
+        }
+
+        /// The value as an integer, accepting floats without a fractional part.
+        pub fn as_i64(&self) -> Option<i64> {
+            match self {
+                ResultValue::String(s) => s.trim().parse().ok(),
+                ResultValue::Integer(n) => Some(*n),
+                ResultValue::Float(n) if n.fract() == 0.0 => Some(*n as i64),
+                _ => None,
+            }
+        }
+
+        /// The value as a Daraja timestamp (East Africa Time), either
+        /// `20191219102115` or `"19.12.2019 11:45:50"`.
+        pub fn as_datetime(&self) -> Option<NaiveDateTime> {
+            let text = match self {
+                ResultValue::String(s) => s.trim().to_string(),
+                ResultValue::Integer(n) => n.to_string(),
+                _ => return None,
+            };
+            NaiveDateTime::parse_from_str(&text, "%Y%m%d%H%M%S")
+                .or_else(|_| NaiveDateTime::parse_from_str(&text, "%d.%m.%Y %H:%M:%S"))
+                .ok()
+        }
+    }
+
+    impl fmt::Display for ResultValue {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            match self {
+                ResultValue::String(s) => f.write_str(s),
+                ResultValue::Integer(n) => write!(f, "{}", n),
+                ResultValue::Float(n) => write!(f, "{}", n),
+                ResultValue::Missing => Ok(()),
+            }
+        }
+    }
+
+    fn string_or_number<'de, D>(deserializer: D) -> Result<String, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let value = serde_json::Value::deserialize(deserializer)?;
+        json_to_string(&value)
+            .ok_or_else(|| serde::de::Error::custom("expected a string or a number"))
+    }
+
+    /// Reads a result code Daraja may send either as a JSON number or as a string.
+    fn code_from_string_or_number<'de, D>(deserializer: D) -> Result<i32, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let code = string_or_number(deserializer)?;
+        code.trim()
+            .parse()
+            .map_err(|_| serde::de::Error::custom(format!("invalid result code {:?}", code)))
+    }
+
+    /// A transaction status query, built with [`TransactionStatusRequest::builder`].
+    #[derive(Debug, Clone)]
+    pub struct TransactionStatusRequest {
+        initiator_name: Option<String>,
+        security_credential: Option<String>,
+        transaction_id: Option<String>,
+        original_conversation_id: Option<String>,
+        short_code: Option<String>,
+        identifier_type: IdentifierType,
+        remarks: String,
+        result_url: Option<String>,
+        queue_timeout_url: Option<String>,
+        occasion: String,
+    }
+
+    /// Builder for [`TransactionStatusRequest`]. The identifier type defaults to
+    /// [`IdentifierType::Shortcode`] and the occasion to empty. Unset business fields fall
+    /// back to the client's [`BusinessProfile`]. Either the transaction ID or the original
+    /// conversation ID is required, as is every other field.
+    #[derive(Debug, Clone, Default)]
+    pub struct TransactionStatusRequestBuilder {
+        initiator_name: Option<String>,
+        security_credential: Option<String>,
+        transaction_id: Option<String>,
+        original_conversation_id: Option<String>,
+        short_code: Option<String>,
+        identifier_type: IdentifierType,
+        remarks: Option<String>,
+        result_url: Option<String>,
+        queue_timeout_url: Option<String>,
+        occasion: Option<String>,
+    }
+
+    impl TransactionStatusRequest {
+        /// Starts building a transaction status query.
+        pub fn builder() -> TransactionStatusRequestBuilder {
+            TransactionStatusRequestBuilder::default()
+        }
+    }
+
+    impl TransactionStatusRequestBuilder {
+        /// The initiator username.
+        pub fn initiator_name(mut self, initiator_name: impl Into<String>) -> Self {
+            self.initiator_name = Some(initiator_name.into());
+            self
+        }
+
+        /// Generated security credential.
+        pub fn security_credential(mut self, security_credential: impl Into<String>) -> Self {
+            self.security_credential = Some(security_credential.into());
+            self
+        }
+
+        /// The transaction ID to query.
+        pub fn transaction_id(mut self, transaction_id: impl Into<String>) -> Self {
+            self.transaction_id = Some(transaction_id.into());
+            self
+        }
+
+        /// The `OriginatorConversationID` of the request to query, for transactions
+        /// whose receipt is not known, e.g. a B2C payment whose result never arrived.
+        pub fn original_conversation_id(mut self, original_conversation_id: impl Into<String>) -> Self {
+            self.original_conversation_id = Some(original_conversation_id.into());
+            self
+        }
+
+        /// Business shortcode, till number or MSISDN that took part in the transaction.
+        pub fn short_code(mut self, short_code: impl Into<String>) -> Self {
+            self.short_code = Some(short_code.into());
+            self
+        }
+
+        /// What kind of party the short code is; defaults to a shortcode.
+        pub fn identifier_type(mut self, identifier_type: IdentifierType) -> Self {
+            self.identifier_type = identifier_type;
+            self
+        }
+
+        /// Request remarks.
+        pub fn remarks(mut self, remarks: impl Into<String>) -> Self {
+            self.remarks = Some(remarks.into());
+            self
+        }
+
+        /// URL to receive the result callback.
+        pub fn result_url(mut self, result_url: impl Into<String>) -> Self {
+            self.result_url = Some(result_url.into());
+            self
+        }
+
+        /// URL for timeout notifications.
+        pub fn queue_timeout_url(mut self, queue_timeout_url: impl Into<String>) -> Self {
+            self.queue_timeout_url = Some(queue_timeout_url.into());
+            self
+        }
+
+        /// Optional occasion description.
+        pub fn occasion(mut self, occasion: impl Into<String>) -> Self {
+            self.occasion = Some(occasion.into());
+            self
+        }
+
+        /// Validates the builder, returning [`MpesaError::InvalidRequest`] listing
+        /// every missing field.
+        pub fn build(self) -> Result<TransactionStatusRequest, MpesaError> {
+            check_required(&[
+                (
+                    "transaction_id or original_conversation_id",
+                    self.transaction_id.is_some() || self.original_conversation_id.is_some(),
+                ),
+                ("remarks", self.remarks.is_some()),
+            ])?;
+
+            Ok(TransactionStatusRequest {
+                initiator_name: self.initiator_name,
+                security_credential: self.security_credential,
+                transaction_id: self.transaction_id,
+                original_conversation_id: self.original_conversation_id,
+                short_code: self.short_code,
+                identifier_type: self.identifier_type,
+                remarks: self.remarks.unwrap_or_default(),
+                result_url: self.result_url,
+                queue_timeout_url: self.queue_timeout_url,
+                occasion: self.occasion.unwrap_or_default(),
+            })
+        }
+    }
+
+    #[derive(Serialize)]
+    struct TransactionStatusPayload {
+        #[serde(rename = "Initiator")]
+        initiator: String,
+        #[serde(rename = "SecurityCredential")]
+        security_credential: String,
+        #[serde(rename = "CommandID")]
+        command_id: String,
+        #[serde(rename = "TransactionID")]
+        transaction_id: String,
+        #[serde(rename = "OriginalConversationID", skip_serializing_if = "Option::is_none")]
+        original_conversation_id: Option<String>,
+        #[serde(rename = "PartyA")]
+        party_a: String,
+        #[serde(rename = "IdentifierType")]
+        identifier_type: String,
+        #[serde(rename = "ResultURL")]
+        result_url: String,
+        #[serde(rename = "QueueTimeOutURL")]
+        queue_timeout_url: String,
+        #[serde(rename = "Remarks")]
+        remarks: String,
+        #[serde(rename = "Occasion")]
+        occasion: String,
+    }
+
+    /// Immediate response from a transaction status query.
+    #[derive(Deserialize)]
+    pub struct TransactionStatusResponse {
+        #[serde(rename = "ConversationID")]
+        pub conversation_id: Option<String>,
+        #[serde(rename = "OriginatorConversationID")]
+        pub originator_conversation_id: Option<String>,
+        #[serde(rename = "ResponseCode")]
+        pub response_code: String,
+        #[serde(rename = "ResponseDescription")]
+        pub response_description: String,
+        #[serde(skip)]
+        pending: Option<PendingResult<TransactionStatusResult>>,
+    }
+
+    impl TransactionStatusResponse {
+        /// Waits up to `timeout` for the result of this query.
+        ///
+        /// Resolves once the result is passed to
+        /// [`CallbackRegistry::resolve_transaction_status`]; returns
+        /// [`MpesaError::Timeout`] if it does not arrive in time.
+        pub async fn wait_for_result(mut self, timeout: Duration) -> Result<TransactionStatusResult, MpesaError> {
+            match self.pending.take() {
+                Some(pending) => pending.wait(timeout).await,
+                None => Err(MpesaError::InvalidRequest(
+                    "response has no ConversationID to wait on".to_string(),
+                )),
+            }
+        }
+    }
+
+    /// Payload Daraja posts to the transaction status `ResultURL`.
+    #[derive(Serialize, Deserialize)]
+    pub struct TransactionStatusResultCallback {
+        #[serde(rename = "Result")]
+        pub result: TransactionStatusResult,
+    }
+
+    /// Result of a transaction status query.
+    #[derive(Serialize, Deserialize, Clone)]
+    pub struct TransactionStatusResult {
+        #[serde(rename = "ResultType")]
+        pub result_type: i32,
+        #[serde(rename = "ResultCode", deserialize_with = "code_from_string_or_number")]
+        pub result_code: i32,
+        #[serde(rename = "ResultDesc")]
+        pub result_desc: String,
+        #[serde(rename = "OriginatorConversationID")]
+        pub originator_conversation_id: String,
+        #[serde(rename = "ConversationID")]
+        pub conversation_id: String,
+        #[serde(rename = "TransactionID")]
+        pub transaction_id: String,
+        /// Present on successful queries only.
+        #[serde(rename = "ResultParameters")]
+        pub result_parameters: Option<ResultParameters>,
+        #[serde(rename = "ReferenceData")]
+        pub reference_data: Option<ReferenceData>,
+    }
+
+    /// Reference data echoed back in a result callback, such as the `Occasion`.
+    #[derive(Serialize, Deserialize, Clone)]
+    pub struct ReferenceData {
+        // Daraja sends a single object when there is only one item.
+        #[serde(rename = "ReferenceItem", deserialize_with = "one_or_many")]
+        pub reference_item: Vec<ReferenceItem>,
+    }
+
+    /// Key-value pair in [`ReferenceData`].
+    #[derive(Serialize, Deserialize, Clone)]
+    pub struct ReferenceItem {
+        #[serde(rename = "Key")]
+        pub key: String,
+        #[serde(rename = "Value", default)]
+        pub value: ResultValue,
+    }
+
+    impl ReferenceData {
+        /// Returns the value of the item called `key`, or `None` if it is absent
+        /// or was sent without a value.
+        pub fn get(&self, key: &str) -> Option<&ResultValue> {
+            self.reference_item
+                .iter()
+                .find(|item| item.key == key)
+                .map(|item| &item.value)
+                .filter(|value| !value.is_missing())
+        }
+    }
+
+    fn one_or_many<'de, D, T>(deserializer: D) -> Result<Vec<T>, D::Error>
+    where
+        D: Deserializer<'de>,
+        T: Deserialize<'de>,
+    {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum OneOrMany<T> {
+            One(T),
+            Many(Vec<T>),
+        }
+
+        Ok(match OneOrMany::deserialize(deserializer)? {
+            OneOrMany::One(item) => vec![item],
+            OneOrMany::Many(items) => items,
+        })
+    }
+
+    /// Summary of a queried transaction, extracted from its result parameters.
+    #[derive(Debug, Clone)]
+    pub struct TransactionSummary {
+        pub receipt_no: String,
+        pub transaction_status: String,
+        pub amount: f64,
+        /// e.g. `"600310 - Safaricom333"`.
+        pub debit_party_name: String,
+        /// e.g. `"254708374149 - John Doe"`.
+        pub credit_party_name: String,
+        pub originator_conversation_id: Option<String>,
+        /// Initiation time as reported by Daraja (East Africa Time).
+        pub initiated_time: Option<NaiveDateTime>,
+        /// Completion time as reported by Daraja (East Africa Time).
+        pub finalised_time: Option<NaiveDateTime>,
+        pub debit_account_type: Option<String>,
+        pub reason_type: Option<String>,
+    }
+
+    impl TransactionStatusResult {
+        /// Whether the query succeeded (`ResultCode` `0`). This says nothing about
+        /// the queried transaction itself; see [`TransactionSummary::transaction_status`].
+        pub fn is_successful(&self) -> bool {
+            self.result_code == 0
+        }
+
+        /// Extracts the transaction summary from a successful query.
+        ///
+        /// Returns [`MpesaError::InvalidCallback`] if the result has no parameters or
+        /// is missing `ReceiptNo`, `TransactionStatus`, `Amount`, `DebitPartyName` or
+        /// `CreditPartyName`.
+        ///
+        /// # Examples
+        /// ```
+        /// use mpesa_daraja::mpesa::TransactionStatusResultCallback;
+        /// let payload = r#"{"Result":{"ResultType":0,"ResultCode":0,
+        ///     "ResultDesc":"The service request is processed successfully.",
+        ///     "OriginatorConversationID":"1551-4112-1","ConversationID":"AG_20180820_000040c0f37a4bd9c5f1",
+        ///     "TransactionID":"MHK61H7L7Q","ReferenceData":{"ReferenceItem":{"Key":"Occasion"}},
+        ///     "ResultParameters":{"ResultParameter":[
+        ///     {"Key":"DebitPartyName","Value":"600310 - Safaricom333"},
+        ///     {"Key":"CreditPartyName","Value":"254708374149 - John Doe"},
+        ///     {"Key":"InitiatedTime","Value":20180820150106},{"Key":"CreditPartyCharges"},
+        ///     {"Key":"TransactionStatus","Value":"Completed"},
+        ///     {"Key":"FinalisedTime","Value":20180820150106},{"Key":"Amount","Value":10},
+        ///     {"Key":"ReceiptNo","Value":"MHK61H7L7Q"}]}}}"#;
+        /// let callback: TransactionStatusResultCallback = serde_json::from_str(payload).unwrap();
+        /// let summary = callback.result.summary().unwrap();
+        /// assert_eq!(summary.transaction_status, "Completed");
+        /// assert_eq!(summary.amount, 10.0);
+        /// ```
+        pub fn summary(&self) -> Result<TransactionSummary, MpesaError> {
+            let parameters = self.result_parameters.as_ref().ok_or_else(|| {
+                MpesaError::InvalidCallback("missing ResultParameters".to_string())
+            })?;
+            let text = |key: &str| parameters.get(key).map(ToString::to_string);
+
+            Ok(TransactionSummary {
+                receipt_no: parameters.required("ReceiptNo")?.to_string(),
+                transaction_status: parameters.required("TransactionStatus")?.to_string(),
+                amount: parameters.required_decimal("Amount")?,
+                debit_party_name: parameters.required("DebitPartyName")?.to_string(),
+                credit_party_name: parameters.required("CreditPartyName")?.to_string(),
+                originator_conversation_id: text("OriginatorConversationID"),
+                initiated_time: parameters.get("InitiatedTime").and_then(ResultValue::as_datetime),
+                finalised_time: parameters.get("FinalisedTime").and_then(ResultValue::as_datetime),
+                debit_account_type: text("DebitAccountType"),
+                reason_type: text("ReasonType"),
+            })
+        }
+    }
+
+    /// A transaction reversal, built with [`ReversalRequest::builder`].
+    #[derive(Debug, Clone)]
+    pub struct ReversalRequest {
+        initiator_name: Option<String>,
+        security_credential: Option<String>,
+        transaction_id: String,
+        amount: u32,
+        short_code: Option<String>,
+        remarks: String,
+        result_url: Option<String>,
+        queue_timeout_url: Option<String>,
+        occasion: String,
+    }
+
+    /// Builder for [`ReversalRequest`]. The occasion defaults to empty and unset business
+    /// fields fall back to the client's [`BusinessProfile`]; every other field is required.
+    #[derive(Debug, Clone, Default)]
+    pub struct ReversalRequestBuilder {
+        initiator_name: Option<String>,
+        security_credential: Option<String>,
+        transaction_id: Option<String>,
+        amount: Option<u32>,
+        short_code: Option<String>,
+        remarks: Option<String>,
+        result_url: Option<String>,
+        queue_timeout_url: Option<String>,
+        occasion: Option<String>,
+    }
+
+    impl ReversalRequest {
+        /// Starts building a transaction reversal.
+        pub fn builder() -> ReversalRequestBuilder {
+            ReversalRequestBuilder::default()
+        }
+    }
+
+    impl ReversalRequestBuilder {
+        /// The initiator username.
+        pub fn initiator_name(mut self, initiator_name: impl Into<String>) -> Self {
+            self.initiator_name = Some(initiator_name.into());
+            self
+        }
+
+        /// Generated security credential.
+        pub fn security_credential(mut self, security_credential: impl Into<String>) -> Self {
+            self.security_credential = Some(security_credential.into());
+            self
+        }
+
+        /// The M-Pesa receipt of the transaction to reverse.
+        pub fn transaction_id(mut self, transaction_id: impl Into<String>) -> Self {
+            self.transaction_id = Some(transaction_id.into());
+            self
+        }
+
+        /// The amount to reverse in KES.
+        pub fn amount(mut self, amount: u32) -> Self {
+            self.amount = Some(amount);
+            self
+        }
+
+        /// Business shortcode that received the transaction.
+        pub fn short_code(mut self, short_code: impl Into<String>) -> Self {
+            self.short_code = Some(short_code.into());
+            self
+        }
+
+        /// Request remarks.
+        pub fn remarks(mut self, remarks: impl Into<String>) -> Self {
+            self.remarks = Some(remarks.into());
+            self
+        }
+
+        /// URL to receive the result callback.
+        pub fn result_url(mut self, result_url: impl Into<String>) -> Self {
+            self.result_url = Some(result_url.into());
+            self
+        }
+
+        /// URL for timeout notifications.
+        pub fn queue_timeout_url(mut self, queue_timeout_url: impl Into<String>) -> Self {
+            self.queue_timeout_url = Some(queue_timeout_url.into());
+            self
+        }
+
+        /// Optional occasion description.
+        pub fn occasion(mut self, occasion: impl Into<String>) -> Self {
+            self.occasion = Some(occasion.into());
+            self
+        }
+
+        /// Validates the builder, returning [`MpesaError::InvalidRequest`] listing
+        /// every missing field.
+        pub fn build(self) -> Result<ReversalRequest, MpesaError> {
+            check_required(&[
+                ("transaction_id", self.transaction_id.is_some()),
+                ("amount", self.amount.is_some()),
+                ("remarks", self.remarks.is_some()),
+            ])?;
+
+            Ok(ReversalRequest {
+                initiator_name: self.initiator_name,
+                security_credential: self.security_credential,
+                transaction_id: self.transaction_id.unwrap_or_default(),
+                amount: self.amount.unwrap_or_default(),
+                short_code: self.short_code,
+                remarks: self.remarks.unwrap_or_default(),
+                result_url: self.result_url,
+                queue_timeout_url: self.queue_timeout_url,
+                occasion: self.occasion.unwrap_or_default(),
+            })
+        }
+    }
+
+    #[derive(Serialize)]
+    struct ReversalPayload {
+        #[serde(rename = "Initiator")]
+        initiator: String,
+        #[serde(rename = "SecurityCredential")]
+        security_credential: String,
+        #[serde(rename = "CommandID")]
+        command_id: String,
+        #[serde(rename = "TransactionID")]
+        transaction_id: String,
+        #[serde(rename = "Amount")]
+        amount: String,
+        #[serde(rename = "ReceiverParty")]
+        receiver_party: String,
+        // Daraja's spelling.
+        #[serde(rename = "RecieverIdentifierType")]
+        receiver_identifier_type: String,
+        #[serde(rename = "ResultURL")]
+        result_url: String,
+        #[serde(rename = "QueueTimeOutURL")]
+        queue_timeout_url: String,
+        #[serde(rename = "Remarks")]
+        remarks: String,
+        #[serde(rename = "Occasion")]
+        occasion: String,
+    }
+
+    /// Immediate response from a transaction reversal request.
+    #[derive(Deserialize)]
+    pub struct ReversalResponse {
+        #[serde(rename = "ConversationID")]
+        pub conversation_id: Option<String>,
+        #[serde(rename = "OriginatorConversationID")]
+        pub originator_conversation_id: Option<String>,
+        #[serde(rename = "ResponseCode")]
+        pub response_code: String,
+        #[serde(rename = "ResponseDescription")]
+        pub response_description: String,
+    }
+
+    /// Payload Daraja posts to the reversal `ResultURL`.
+    #[derive(Serialize, Deserialize)]
+    pub struct ReversalResultCallback {
+        #[serde(rename = "Result")]
+        pub result: ReversalResult,
+    }
+
+    /// Final result of a transaction reversal.
+    #[derive(Serialize, Deserialize)]
+    pub struct ReversalResult {
+        #[serde(rename = "ResultType")]
+        pub result_type: i32,
+        #[serde(rename = "ResultCode", deserialize_with = "code_from_string_or_number")]
+        pub result_code: i32,
+        #[serde(rename = "ResultDesc")]
+        pub result_desc: String,
+        #[serde(rename = "OriginatorConversationID")]
+        pub originator_conversation_id: String,
+        #[serde(rename = "ConversationID")]
+        pub conversation_id: String,
+        #[serde(rename = "TransactionID")]
+        pub transaction_id: String,
+        /// Present on successful reversals only.
+        #[serde(rename = "ResultParameters")]
+        pub result_parameters: Option<ResultParameters>,
+    }
+
+    impl ReversalResult {
+        /// Whether the transaction was reversed (`ResultCode` `0`).
+        pub fn is_successful(&self) -> bool {
+            self.result_code == 0
+        }
+    }
+
+    /// Builds the base64 `shortcode + passkey + timestamp` password used by the
+    /// STK Push endpoints, returning it with the timestamp it was built from.
+    fn stk_password(short_code: &str, passkey: &str) -> (String, String) {
+        let timestamp = Utc::now().format("%Y%m%d%H%M%S").to_string();
+        let password = general_purpose::STANDARD.encode(
+            format!("{}{}{}", short_code, passkey, timestamp)
+        );
+        (password, timestamp)
+    }
+
+    impl_acknowledgement!(
+        StkPushResponse,
+        StkPushQueryResponse,
+        C2bRegisterUrlResponse,
+        C2bSimulateResponse,
+        B2cResponse,
+        B2bResponse,
+        BalanceQueryResponse,
+        TransactionStatusResponse,
+        ReversalResponse,
+    );
+
+    impl MpesaClient {
+        /// Creates a new MPESA client.
+        ///
+        /// # Arguments
+        /// * `consumer_key` - The API consumer key from Safaricom.
+        /// * `consumer_secret` - The API consumer secret from Safaricom.
+        /// * `environment` - [`Environment::Sandbox`], [`Environment::Production`] or a custom base URL.
+        ///
+        /// # Examples
+        /// ```
+        /// use mpesa_daraja::mpesa::{Environment, MpesaClient};
+        /// let client = MpesaClient::new("consumer_key", "consumer_secret", Environment::Sandbox);
+        /// ```
+        pub fn new(consumer_key: &str, consumer_secret: &str, environment: Environment) -> Self {
+            Self::builder(consumer_key, consumer_secret)
+                .environment(environment)
+                .build()
+        }
+
+        /// Creates a client from `MPESA_*` environment variables.
+        ///
+        /// | Variable | |
+        /// |---|---|
+        /// | `MPESA_CONSUMER_KEY` | Required. |
+        /// | `MPESA_CONSUMER_SECRET` | Required. |
+        /// | `MPESA_ENVIRONMENT` | `sandbox` (default), `production` or a custom base URL. |
+        /// | `MPESA_SHORT_CODE` | Business shortcode. |
+        /// | `MPESA_PASSKEY` | STK Push passkey. |
+        /// | `MPESA_INITIATOR_NAME` | Initiator username. |
+        /// | `MPESA_SECURITY_CREDENTIAL` | Pre-generated security credential. |
+        /// | `MPESA_INITIATOR_PASSWORD` | Used with `MPESA_CERT_PATH` to generate the credential when `MPESA_SECURITY_CREDENTIAL` is unset. |
+        /// | `MPESA_CERT_PATH` | Certificate path; defaults as in [`MpesaClient::generate_security_credential`]. |
+        /// | `MPESA_CALLBACK_URL` | STK Push callback URL. |
+        /// | `MPESA_RESULT_URL` | Result URL for B2C, B2B, balance, status and reversal requests. |
+        /// | `MPESA_QUEUE_TIMEOUT_URL` | Timeout URL for the same requests. |
+        ///
+        /// Everything but the consumer key and secret goes into the client's
+        /// [`BusinessProfile`]. Returns [`MpesaError::Config`] listing every missing
+        /// required variable.
+        pub fn from_env() -> Result<Self, MpesaError> {
+            MpesaConfig::from_env()
+                .into_client(|field| format!("{}{}", ENV_PREFIX, field.to_uppercase()))
+        }
+
+        /// Creates a client from a `.toml` or `.json` file whose keys are the
+        /// [`MpesaConfig`] field names, e.g. `consumer_key = "..."`.
+        ///
+        /// Returns [`MpesaError::Config`] if the file cannot be read or parsed, or
+        /// listing every missing required key.
+        pub fn from_config(path: impl AsRef<Path>) -> Result<Self, MpesaError> {
+            MpesaConfig::from_file(path.as_ref())?.into_client(str::to_string)
+        }
+
+        /// Starts building a client, e.g. to set a [`BusinessProfile`], a
+        /// preconfigured HTTP client or a custom [`Transport`].
+        ///
+        /// # Arguments
+        /// * `consumer_key` - The API consumer key from Safaricom.
+        /// * `consumer_secret` - The API consumer secret from Safaricom.
+        pub fn builder(consumer_key: &str, consumer_secret: &str) -> MpesaClientBuilder {
+            MpesaClientBuilder {
+                consumer_key: consumer_key.to_string(),
+                consumer_secret: consumer_secret.to_string(),
+                environment: Environment::Sandbox,
+                profile: BusinessProfile::default(),
+                transport: ReqwestTransport::default(),
+                store: NoStore,
+            }
+        }
+
+        /// Generates a security credential for B2C, balance, and transaction status APIs.
+        ///
+        /// # Arguments
+        /// * `initiator_password` - The initiator's password.
+        /// * `is_production` - Whether to use the production certificate.
+        /// * `cert_path` - Optional path to the certificate file; defaults to "certs/production.cer" or "certs/sandbox.cer".
+        pub fn generate_security_credential(
+            initiator_password: &str,
+            is_production: bool,
+            cert_path: Option<&str>,
+        ) -> Result<String, MpesaError> {
+            let default_path = if is_production {
+                "certs/production.cer"
+            } else {
+                "certs/sandbox.cer"
+            };
+            let path = cert_path.unwrap_or(default_path);
+
+            let cert_content = fs::read(path)?;
+            let cert = X509::from_pem(&cert_content)?;
+            let public_key = cert.public_key()?;
+            let rsa = public_key.rsa()?;
+
+            let mut encrypted = vec![0; rsa.size() as usize];
+            let len = rsa.public_encrypt(
+                initiator_password.as_bytes(),
+                &mut encrypted,
+                Padding::PKCS1,
+            )?;
+
+            Ok(general_purpose::STANDARD.encode(&encrypted[..len]))
+        }
+    }
+
+    impl<T: Transport, S: TransactionStore> MpesaClient<T, S> {
+        /// The registry that STK Push and B2C responses wait on for their callbacks;
+        /// shared by all clones of this client.
+        pub fn callbacks(&self) -> &CallbackRegistry {
+            &self.inner.callbacks
+        }
+
+        /// The store STK Pushes and B2C payments are recorded in.
+        pub fn store(&self) -> &S {
+            &self.inner.store
+        }
+
+        /// Handles an STK Push callback: updates its record in the store and hands it
+        /// to the request waiting on it, see [`StkPushResponse::wait_for_result`].
+        ///
+        /// A [`reconciled`](StkCallback::reconciled) callback only applies to a push
+        /// that is still pending, so it never overwrites the result of a real callback;
+        /// otherwise nothing happens and `false` is returned.
+        pub async fn complete_stk_push(&self, callback: &StkCallback) -> Result<bool, MpesaError> {
+            let store = &self.inner.store;
+            let update = TransactionUpdate::from_stk_callback(callback);
+            if callback.reconciled {
+                if !store.update_if_pending(&callback.checkout_request_id, update).await? {
+                    return Ok(false);
+                }
+            } else {
+                store.update(&callback.checkout_request_id, update).await?;
+            }
+            self.inner.callbacks.resolve_stk(callback);
+            Ok(true)
+        }
+
+        /// Handles a B2C result: updates its record in the store and hands it to the
+        /// payment waiting on it, see [`B2cResponse::wait_for_result`].
+        pub async fn complete_b2c_payment(&self, result: &B2cResult) -> Result<(), MpesaError> {
+            let update = TransactionUpdate::from_b2c_result(result);
+            let store = &self.inner.store;
+            if !store.update(&result.originator_conversation_id, update.clone()).await? {
+                store.update(&result.conversation_id, update).await?;
+            }
+            self.inner.callbacks.resolve_b2c(result);
+            Ok(())
+        }
+
+        /// Handles a transaction status result by handing it to the query waiting on
+        /// it, see [`TransactionStatusResponse::wait_for_result`].
+        pub fn complete_transaction_status(&self, result: &TransactionStatusResult) {
+            self.inner.callbacks.resolve_transaction_status(result);
+        }
+
+        /// Records an accepted request, logging rather than returning a store
+        /// failure so the caller still learns the IDs of a request Daraja accepted.
+        async fn record(&self, record: TransactionRecord) {
+            let request_id = record.request_id.clone();
+            if let Err(err) = self.inner.store.insert(record).await {
+                tracing::error!("Failed to record M-Pesa request {}: {}", request_id, err);
+            }
+        }
+
+        /// Returns a cached access token, fetching a new one when none is cached
+        /// or the cached one is about to expire.
+        ///
+        /// The cache lock is held while refreshing, so concurrent callers wait for
+        /// a single request to `/oauth/v1/generate` instead of issuing their own.
+        async fn get_access_token(&self) -> Result<String, MpesaError> {
+            let mut cached = self.inner.token.lock().await;
+            if let Some(token) = cached.as_ref().filter(|token| token.is_fresh()) {
+                return Ok(token.access_token.clone());
+            }
+
+            let token = self.fetch_access_token().await?;
+            let access_token = token.access_token.clone();
+            *cached = Some(token);
+            Ok(access_token)
+        }
+
+        /// Drops the cached token if it is still the one that was rejected, leaving
+        /// any token already refreshed by another task in place.
+        async fn invalidate_access_token(&self, rejected: &str) {
+            let mut cached = self.inner.token.lock().await;
+            if cached.as_ref().is_some_and(|token| token.access_token == rejected) {
+                *cached = None;
+            }
+        }
+
+        async fn fetch_access_token(&self) -> Result<CachedToken, MpesaError> {
+            let auth = format!("{}:{}", self.inner.consumer_key, self.inner.consumer_secret);
+            let auth_encoded = general_purpose::STANDARD.encode(auth);
+
+            let url = self.url("/oauth/v1/generate?grant_type=client_credentials");
+
+            let response = self
+                .inner
+                .transport
+                .send(HttpRequest {
+                    method: Method::GET,
+                    url,
+                    authorization: format!("Basic {}", auth_encoded),
+                    body: None,
+                })
+                .await?;
+
+            let status = response.status;
+            let text = response.body;
+            if !status.is_success() {
+                return Err(MpesaError::Auth {
+                    status: status.as_u16(),
+                    body: text,
+                });
+            }
+
+            let token_data: AccessTokenResponse = match serde_json::from_str(&text) {
+                Ok(token_data) => token_data,
+                Err(source) => return Err(MpesaError::Decode { body: text, source }),
+            };
+            // An unparseable expiry leaves the token stale straight away, so the
+            // next call fetches a new one as before.
+            let expires_in = token_data.expires_in.trim().parse().unwrap_or(0);
+            Ok(CachedToken {
+                access_token: token_data.access_token,
+                expires_at: Instant::now() + Duration::from_secs(expires_in),
+            })
+        }
+
+        fn resolve_initiator(
+            &self,
+            initiator_name: &Option<String>,
+            security_credential: &Option<String>,
+            short_code: &Option<String>,
+            result_url: &Option<String>,
+            queue_timeout_url: &Option<String>,
+        ) -> Result<InitiatorFields, MpesaError> {
+            let mut fields = ProfileFallback::new(&self.inner.profile);
+            let resolved = InitiatorFields {
+                initiator_name: fields.resolve("initiator_name", initiator_name, |p| {
+                    &p.initiator_name
+                }),
+                security_credential: fields.resolve("security_credential", security_credential, |p| {
+                    &p.security_credential
+                }),
+                short_code: fields.resolve("short_code", short_code, |p| &p.short_code),
+                result_url: fields.resolve("result_url", result_url, |p| &p.result_url),
+                queue_timeout_url: fields.resolve("queue_timeout_url", queue_timeout_url, |p| {
+                    &p.queue_timeout_url
+                }),
+            };
+            fields.finish()?;
+            Ok(resolved)
+        }
+
+        /// Returns `short_code`, or the profile's when it is `None`.
+        fn resolve_short_code(&self, short_code: Option<&str>) -> Result<String, MpesaError> {
+            let mut fields = ProfileFallback::new(&self.inner.profile);
+            let short_code =
+                fields.resolve("short_code", &short_code.map(str::to_string), |p| &p.short_code);
+            fields.finish()?;
+            Ok(short_code)
+        }
+
+        /// Joins `path` onto the base URL of the client's environment.
+        fn url(&self, path: &str) -> String {
+            format!("{}{}", self.inner.environment.base_url(), path)
+        }
+
+        /// Posts `body` to `url` with a bearer token and returns the response status
+        /// and raw text.
+        ///
+        /// A `401 Unauthorized` invalidates the cached token and the request is retried
+        /// once with a freshly fetched one; a second `401` is reported as
+        /// [`MpesaError::Auth`].
+        async fn post_authorized<B: Serialize>(
+            &self,
+            url: &str,
+            body: &B,
+        ) -> Result<(StatusCode, String), MpesaError> {
+            let body = serde_json::to_string(body)
+                .map_err(|err| MpesaError::InvalidRequest(err.to_string()))?;
+            let access_token = self.get_access_token().await?;
+            let mut response = self.send_authorized(url, &access_token, &body).await?;
+
+            if response.status == StatusCode::UNAUTHORIZED {
+                self.invalidate_access_token(&access_token).await;
+                let access_token = self.get_access_token().await?;
+                response = self.send_authorized(url, &access_token, &body).await?;
+            }
+
+            let status = response.status;
+            let text = response.body;
+            if status == StatusCode::UNAUTHORIZED {
+                return Err(MpesaError::Auth {
+                    status: status.as_u16(),
+                    body: text,
+                });
+            }
+
+            Ok((status, text))
+        }
+
+        async fn send_authorized(
+            &self,
+            url: &str,
+            access_token: &str,
+            body: &str,
+        ) -> Result<HttpResponse, MpesaError> {
+            self.inner
+                .transport
+                .send(HttpRequest {
+                    method: Method::POST,
+                    url: url.to_string(),
+                    authorization: format!("Bearer {}", access_token),
+                    body: Some(body.to_string()),
+                })
+                .await
+        }
+
+        /// Initiates an STK Push (C2B) transaction, prompting the user to enter their PIN.
+        ///
+        /// Thin wrapper around [`MpesaClient::send_stk_push`].
+        ///
+        /// # Arguments
+        /// * `phone_number` - The customer's phone number (e.g., "2547XXXXXXXX").
+        /// * `amount` - The amount to charge in KES.
+        /// * `account_reference` - A reference for the transaction (e.g., invoice number).
+        /// * `transaction_desc` - A description of the transaction.
+        /// * `callback_url` - URL to receive the transaction result.
+        /// * `short_code` - The business shortcode.
+        /// * `passkey` - The passkey from Safaricom.
+        #[allow(clippy::too_many_arguments)]
+        pub async fn stk_push(
+            &self,
+            phone_number: &str,
+            amount: u32,
+            account_reference: &str,
+            transaction_desc: &str,
+            callback_url: &str,
+            short_code: &str,
+            passkey: &str,
+        ) -> Result<StkPushResponse, MpesaError> {
+            let request = StkPushRequest::builder()
+                .phone_number(phone_number)
+                .amount(amount)
+                .account_reference(account_reference)
+                .transaction_desc(transaction_desc)
+                .callback_url(callback_url)
+                .short_code(short_code)
+                .passkey(passkey)
+                .build()?;
+            self.send_stk_push(&request).await
+        }
+
+        /// Initiates an STK Push (C2B) transaction, prompting the user to enter their PIN.
+        ///
+        /// # Examples
+        /// ```no_run
+        /// use mpesa_daraja::mpesa::{MpesaClient, StkPushRequest};
+        /// # async fn run(client: MpesaClient) -> Result<(), mpesa_daraja::mpesa::MpesaError> {
+        /// let request = StkPushRequest::builder()
+        ///     .phone_number("254708374149")
+        ///     .amount(1)
+        ///     .account_reference("INV-001")
+        ///     .transaction_desc("Order 1")
+        ///     .callback_url("https://example.com/stk/callback")
+        ///     .short_code("174379")
+        ///     .passkey("...")
+        ///     .build()?;
+        /// let response = client.send_stk_push(&request).await?;
+        /// # Ok(())
+        /// # }
+        /// ```
+        pub async fn send_stk_push(
+            &self,
+            request: &StkPushRequest,
+        ) -> Result<StkPushResponse, MpesaError> {
+            let mut fields = ProfileFallback::new(&self.inner.profile);
+            let short_code = fields.resolve("short_code", &request.short_code, |p| &p.short_code);
+            let passkey = fields.resolve("passkey", &request.passkey, |p| &p.passkey);
+            let callback_url =
+                fields.resolve("callback_url", &request.callback_url, |p| &p.callback_url);
+            fields.finish()?;
+
+            let (password, timestamp) = stk_password(&short_code, &passkey);
+
+            let url = self.url("/mpesa/stkpush/v1/processrequest");
+
+            let request_body = StkPushPayload {
+                business_short_code: short_code.clone(),
+                password,
+                timestamp,
+                transaction_type: "CustomerPayBillOnline".to_string(),
+                amount: request.amount.to_string(),
+                party_a: request.phone_number.clone(),
+                party_b: short_code,
+                phone_number: request.phone_number.clone(),
+                callback_url,
+                account_reference: request.account_reference.clone(),
+                transaction_desc: request.transaction_desc.clone(),
+            };
+
+            let (status, text) = self.post_authorized(&url, &request_body).await?;
+
+            // Log raw response (very useful for debugging)
+            tracing::info!("M-Pesa STK Push Raw Response: {}", text);
+
+            let mut response: StkPushResponse = parse_response(status, text)?;
+            response.pending = response
+                .checkout_request_id
+                .clone()
+                .map(|id| self.inner.callbacks.stk.register(id));
+            if let Some(checkout_request_id) = &response.checkout_request_id {
+                let now = Utc::now();
+                self.record(TransactionRecord {
+                    kind: TransactionKind::StkPush,
+                    request_id: checkout_request_id.clone(),
+                    conversation_id: response.merchant_request_id.clone(),
+                    amount: request.amount,
+                    party: request.phone_number.clone(),
+                    short_code: request_body.business_short_code,
+                    status: TransactionStatus::Pending,
+                    result_code: None,
+                    result_desc: None,
+                    receipt: None,
+                    created_at: now,
+                    updated_at: now,
+                })
+                .await;
+            }
+            Ok(response)
+        }
+
+        /// Queries the outcome of an earlier STK Push, e.g. when its callback never arrived.
+        ///
+        /// While the customer has not yet responded Daraja answers with an
+        /// [`MpesaError::Api`] error (`errorCode` `"500.001.1001"`), so callers polling
+        /// this endpoint should retry on that code.
+        ///
+        /// # Arguments
+        /// * `checkout_request_id` - The `CheckoutRequestID` returned by `stk_push`.
+        /// * `short_code` - The business shortcode used for the STK Push; `None` to use the
+        ///   client's [`BusinessProfile`].
+        /// * `passkey` - The passkey from Safaricom; `None` to use the client's profile.
+        pub async fn stk_push_query(
+            &self,
+            checkout_request_id: &str,
+            short_code: Option<&str>,
+            passkey: Option<&str>,
+        ) -> Result<StkPushQueryResponse, MpesaError> {
+            let mut fields = ProfileFallback::new(&self.inner.profile);
+            let short_code =
+                fields.resolve("short_code", &short_code.map(str::to_string), |p| &p.short_code);
+            let passkey = fields.resolve("passkey", &passkey.map(str::to_string), |p| &p.passkey);
+            fields.finish()?;
+
+            let (password, timestamp) = stk_password(&short_code, &passkey);
+
+            let url = self.url("/mpesa/stkpushquery/v1/query");
+
+            let request_body = StkPushQueryPayload {
+                business_short_code: short_code,
+                password,
+                timestamp,
+                checkout_request_id: checkout_request_id.to_string(),
+            };
+
+            let (status, text) = self.post_authorized(&url, &request_body).await?;
+            parse_response(status, text)
+        }
+
+        /// Registers the confirmation and validation URLs for a C2B shortcode.
+        ///
+        /// # Arguments
+        /// * `short_code` - The paybill or till shortcode; `None` to use the client's
+        ///   [`BusinessProfile`].
+        /// * `response_type` - What to do with payments when the validation URL is unreachable.
+        /// * `confirmation_url` - URL to receive completed payments.
+        /// * `validation_url` - URL to validate payments before they complete.
+        /// * `version` - The C2B API version to register with.
+        pub async fn register_c2b_urls(
+            &self,
+            short_code: Option<&str>,
+            response_type: C2bResponseType,
+            confirmation_url: &str,
+            validation_url: &str,
+            version: C2bVersion,
+        ) -> Result<C2bRegisterUrlResponse, MpesaError> {
+            let short_code = self.resolve_short_code(short_code)?;
+            let url = match version {
+                C2bVersion::V1 => self.url("/mpesa/c2b/v1/registerurl"),
+                C2bVersion::V2 => self.url("/mpesa/c2b/v2/registerurl"),
+            };
+
+            let request_body = C2bRegisterUrlPayload {
+                short_code,
+                response_type,
+                confirmation_url: confirmation_url.to_string(),
+                validation_url: validation_url.to_string(),
+            };
+
+            let (status, text) = self.post_authorized(&url, &request_body).await?;
+            parse_response(status, text)
+        }
+
+        /// Simulates a customer paying a paybill or till, triggering the registered
+        /// C2B validation and confirmation callbacks. Sandbox only.
+        ///
+        /// Returns [`MpesaError::InvalidRequest`] without contacting Daraja when the
+        /// client is configured for production. A [`Environment::Custom`] base URL is
+        /// allowed so the call can be pointed at a mock server.
+        ///
+        /// # Arguments
+        /// * `short_code` - The paybill or till shortcode; `None` to use the client's
+        ///   [`BusinessProfile`].
+        /// * `command_id` - Whether to simulate a paybill or buy goods payment.
+        /// * `amount` - The amount to pay in KES.
+        /// * `msisdn` - The paying phone number (e.g., "254708374149").
+        /// * `bill_ref_number` - The account number; `None` for buy goods payments.
+        pub async fn simulate_c2b(
+            &self,
+            short_code: Option<&str>,
+            command_id: C2bCommand,
+            amount: u32,
+            msisdn: &str,
+            bill_ref_number: Option<&str>,
+        ) -> Result<C2bSimulateResponse, MpesaError> {
+            if self.inner.environment == Environment::Production {
+                return Err(MpesaError::InvalidRequest(
+                    "C2B simulate is only available in the sandbox".to_string(),
+                ));
+            }
+            let short_code = self.resolve_short_code(short_code)?;
+            let url = self.url("/mpesa/c2b/v1/simulate");
+
+            let request_body = C2bSimulatePayload {
+                short_code,
+                command_id,
+                amount: amount.to_string(),
+                msisdn: msisdn.to_string(),
+                bill_ref_number: bill_ref_number.map(str::to_string),
+            };
+
+            let (status, text) = self.post_authorized(&url, &request_body).await?;
+            parse_response(status, text)
+        }
+
+        /// Initiates a B2C payment to a customer's phone number.
+        ///
+        /// Sends a `BusinessPayment` through [`MpesaClient::send_b2c_payment`], which
+        /// also handles salary and promotion payments.
+        ///
+        /// # Arguments
+        /// * `phone_number` - Recipient's phone number (e.g., "2547XXXXXXXX").
+        /// * `amount` - Amount to send in KES.
+        /// * `remarks` - Transaction remarks.
+        /// * `result_url` - URL to receive the result callback.
+        /// * `queue_timeout_url` - URL for timeout notifications.
+        /// * `initiator_name` - The initiator username.
+        /// * `security_credential` - Generated security credential.
+        /// * `short_code` - Business shortcode.
+        /// * `occasion` - Optional occasion description.
+        #[allow(clippy::too_many_arguments)]
+        pub async fn business_payment(
+            &self,
+            phone_number: &str,
+            amount: u32,
+            remarks: &str,
+            result_url: &str,
+            queue_timeout_url: &str,
+            initiator_name: &str,
+            security_credential: &str,
+            short_code: &str,
+            occasion: &str,
+        ) -> Result<B2cResponse, MpesaError> {
+            let request = B2cRequest::builder()
+                .command(B2cCommand::BusinessPayment)
+                .phone_number(phone_number)
+                .amount(amount)
+                .remarks(remarks)
+                .result_url(result_url)
+                .queue_timeout_url(queue_timeout_url)
+                .initiator_name(initiator_name)
+                .security_credential(security_credential)
+                .short_code(short_code)
+                .occasion(occasion)
+                .build()?;
+            self.send_b2c_payment(&request).await
+        }
+
+        /// Sends a B2C payment of any [`B2cCommand`] type.
+        ///
+        /// # Examples
+        /// ```no_run
+        /// use mpesa_daraja::mpesa::{B2cCommand, B2cRequest, MpesaClient};
+        /// # async fn run(client: MpesaClient) -> Result<(), mpesa_daraja::mpesa::MpesaError> {
+        /// let request = B2cRequest::builder()
+        ///     .command(B2cCommand::SalaryPayment)
+        ///     .phone_number("254708374149")
+        ///     .amount(1500)
+        ///     .remarks("October salary")
+        ///     .result_url("https://example.com/b2c/result")
+        ///     .queue_timeout_url("https://example.com/b2c/timeout")
+        ///     .initiator_name("testapi")
+        ///     .security_credential("...")
+        ///     .short_code("600000")
+        ///     .build()?;
+        /// let response = client.send_b2c_payment(&request).await?;
+        /// # Ok(())
+        /// # }
+        /// ```
+        pub async fn send_b2c_payment(
+            &self,
+            request: &B2cRequest,
+        ) -> Result<B2cResponse, MpesaError> {
+            let InitiatorFields {
+                initiator_name,
+                security_credential,
+                short_code,
+                result_url,
+                queue_timeout_url,
+            } = self.resolve_initiator(
+                &request.initiator_name,
+                &request.security_credential,
+                &request.short_code,
+                &request.result_url,
+                &request.queue_timeout_url,
+            )?;
+
+            let url = self.url("/mpesa/b2c/v1/paymentrequest");
+
+            let request_body = B2cPayload {
+                initiator_name,
+                security_credential,
+                command_id: request.command,
+                amount: request.amount.to_string(),
+                party_a: short_code,
+                party_b: request.phone_number.clone(),
+                remarks: request.remarks.clone(),
+                queue_timeout_url,
+                result_url,
+                occasion: request.occasion.clone(),
+            };
+
+            let (status, text) = self.post_authorized(&url, &request_body).await?;
+            let mut response: B2cResponse = parse_response(status, text)?;
+            let request_id = response
+                .originator_conversation_id
+                .clone()
+                .or_else(|| response.conversation_id.clone());
+            response.pending = request_id
+                .clone()
+                .map(|id| self.inner.callbacks.b2c.register(id));
+            if let Some(request_id) = request_id {
+                let now = Utc::now();
+                self.record(TransactionRecord {
+                    kind: TransactionKind::B2c,
+                    request_id,
+                    conversation_id: response.conversation_id.clone(),
+                    amount: request.amount,
+                    party: request.phone_number.clone(),
+                    short_code: request_body.party_a,
+                    status: TransactionStatus::Pending,
+                    result_code: None,
+                    result_desc: None,
+                    receipt: None,
+                    created_at: now,
+                    updated_at: now,
+                })
+                .await;
+            }
+            Ok(response)
+        }
+
+        /// Pays another business's paybill or till from a shortcode.
+        ///
+        /// Thin wrapper around [`MpesaClient::send_b2b_payment`]; the identifier types
+        /// follow from `command_id`, see [`B2bCommand::receiver_identifier_type`].
+        ///
+        /// # Arguments
+        /// * `command_id` - `BusinessPayBill` or `BusinessBuyGoods`.
+        /// * `amount` - Amount to send in KES.
+        /// * `receiver_short_code` - The paybill or till receiving the payment.
+        /// * `account_reference` - The account number at the receiving paybill.
+        /// * `requester` - Optional phone number of the customer the payment is made on behalf of.
+        /// * `remarks` - Transaction remarks.
+        /// * `result_url` - URL to receive the result callback.
+        /// * `queue_timeout_url` - URL for timeout notifications.
+        /// * `initiator_name` - The initiator username.
+        /// * `security_credential` - Generated security credential.
+        /// * `short_code` - Business shortcode sending the payment.
+        #[allow(clippy::too_many_arguments)]
+        pub async fn b2b_payment(
+            &self,
+            command_id: B2bCommand,
+            amount: u32,
+            receiver_short_code: &str,
+            account_reference: &str,
+            requester: Option<&str>,
+            remarks: &str,
+            result_url: &str,
+            queue_timeout_url: &str,
+            initiator_name: &str,
+            security_credential: &str,
+            short_code: &str,
+        ) -> Result<B2bResponse, MpesaError> {
+            let mut builder = B2bRequest::builder()
+                .command(command_id)
+                .amount(amount)
+                .receiver_short_code(receiver_short_code)
+                .account_reference(account_reference)
+                .remarks(remarks)
+                .result_url(result_url)
+                .queue_timeout_url(queue_timeout_url)
+                .initiator_name(initiator_name)
+                .security_credential(security_credential)
+                .short_code(short_code);
+            if let Some(requester) = requester {
+                builder = builder.requester(requester);
+            }
+            self.send_b2b_payment(&builder.build()?).await
+        }
+
+        /// Pays another business's paybill or till from a shortcode.
+        pub async fn send_b2b_payment(
+            &self,
+            request: &B2bRequest,
+        ) -> Result<B2bResponse, MpesaError> {
+            let InitiatorFields {
+                initiator_name,
+                security_credential,
+                short_code,
+                result_url,
+                queue_timeout_url,
+            } = self.resolve_initiator(
+                &request.initiator_name,
+                &request.security_credential,
+                &request.short_code,
+                &request.result_url,
+                &request.queue_timeout_url,
+            )?;
+
+            let url = self.url("/mpesa/b2b/v1/paymentrequest");
+
+            let request_body = B2bPayload {
+                initiator: initiator_name,
+                security_credential,
+                command_id: request.command,
+                sender_identifier_type: request.sender_identifier_type.code().to_string(),
+                receiver_identifier_type: request.receiver_identifier_type.code().to_string(),
+                amount: request.amount.to_string(),
+                party_a: short_code,
+                party_b: request.receiver_short_code.clone(),
+                account_reference: request.account_reference.clone(),
+                requester: request.requester.clone(),
+                remarks: request.remarks.clone(),
+                queue_timeout_url,
+                result_url,
+            };
+
+            let (status, text) = self.post_authorized(&url, &request_body).await?;
+            parse_response(status, text)
+        }
+
+        /// Queries the account balance for a shortcode or till.
+        ///
+        /// Thin wrapper around [`MpesaClient::send_balance_query`].
+        ///
+        /// # Arguments
+        /// * `initiator_name` - The initiator username.
+        /// * `security_credential` - Generated security credential.
+        /// * `short_code` - Business shortcode, till number or MSISDN being queried.
+        /// * `identifier_type` - What kind of party `short_code` is.
+        /// * `remarks` - Request remarks.
+        /// * `queue_timeout_url` - URL for timeout notifications.
+        /// * `result_url` - URL to receive the result callback.
+        #[allow(clippy::too_many_arguments)]
+        pub async fn check_balance(
+            &self,
+            initiator_name: &str,
+            security_credential: &str,
+            short_code: &str,
+            identifier_type: IdentifierType,
+            remarks: &str,
+            queue_timeout_url: &str,
+            result_url: &str,
+        ) -> Result<BalanceQueryResponse, MpesaError> {
+            let request = BalanceRequest::builder()
+                .initiator_name(initiator_name)
+                .security_credential(security_credential)
+                .short_code(short_code)
+                .identifier_type(identifier_type)
+                .remarks(remarks)
+                .queue_timeout_url(queue_timeout_url)
+                .result_url(result_url)
+                .build()?;
+            self.send_balance_query(&request).await
+        }
+
+        /// Queries the account balance for a shortcode or till.
+        pub async fn send_balance_query(
+            &self,
+            request: &BalanceRequest,
+        ) -> Result<BalanceQueryResponse, MpesaError> {
+            let InitiatorFields {
+                initiator_name,
+                security_credential,
+                short_code,
+                result_url,
+                queue_timeout_url,
+            } = self.resolve_initiator(
+                &request.initiator_name,
+                &request.security_credential,
+                &request.short_code,
+                &request.result_url,
+                &request.queue_timeout_url,
+            )?;
+
+            let url = self.url("/mpesa/accountbalance/v1/query");
+
+            let request_body = BalancePayload {
+                initiator: initiator_name,
+                security_credential,
+                command_id: "AccountBalance".to_string(),
+                party_a: short_code,
+                identifier_type: request.identifier_type.code().to_string(),
+                remarks: request.remarks.clone(),
+                queue_timeout_url,
+                result_url,
+            };
+
+            let (status, text) = self.post_authorized(&url, &request_body).await?;
+            parse_response(status, text)
+        }
+
+        /// Checks the status of a previous transaction.
+        ///
+        /// Thin wrapper around [`MpesaClient::send_transaction_status_query`].
+        ///
+        /// # Arguments
+        /// * `initiator_name` - The initiator username.
+        /// * `security_credential` - Generated security credential.
+        /// * `transaction_id` - The transaction ID to query.
+        /// * `short_code` - Business shortcode, till number or MSISDN that took part in the transaction.
+        /// * `identifier_type` - What kind of party `short_code` is.
+        /// * `remarks` - Request remarks.
+        /// * `result_url` - URL to receive the result callback.
+        /// * `queue_timeout_url` - URL for timeout notifications.
+        /// * `occasion` - Optional occasion description.
+        #[allow(clippy::too_many_arguments)]
+        pub async fn check_transaction_status(
+            &self,
+            initiator_name: &str,
+            security_credential: &str,
+            transaction_id: &str,
+            short_code: &str,
+            identifier_type: IdentifierType,
+            remarks: &str,
+            result_url: &str,
+            queue_timeout_url: &str,
+            occasion: &str,
+        ) -> Result<TransactionStatusResponse, MpesaError> {
+            let request = TransactionStatusRequest::builder()
+                .initiator_name(initiator_name)
+                .security_credential(security_credential)
+                .transaction_id(transaction_id)
+                .short_code(short_code)
+                .identifier_type(identifier_type)
+                .remarks(remarks)
+                .result_url(result_url)
+                .queue_timeout_url(queue_timeout_url)
+                .occasion(occasion)
+                .build()?;
+            self.send_transaction_status_query(&request).await
+        }
+
+        /// Checks the status of a previous transaction.
+        pub async fn send_transaction_status_query(
+            &self,
+            request: &TransactionStatusRequest,
+        ) -> Result<TransactionStatusResponse, MpesaError> {
+            let InitiatorFields {
+                initiator_name,
+                security_credential,
+                short_code,
+                result_url,
+                queue_timeout_url,
+            } = self.resolve_initiator(
+                &request.initiator_name,
+                &request.security_credential,
+                &request.short_code,
+                &request.result_url,
+                &request.queue_timeout_url,
+            )?;
+
+            let url = self.url("/mpesa/transactionstatus/v1/query");
+
+            let request_body = TransactionStatusPayload {
+                initiator: initiator_name,
+                security_credential,
+                command_id: "TransactionStatusQuery".to_string(),
+                transaction_id: request.transaction_id.clone().unwrap_or_default(),
+                original_conversation_id: request.original_conversation_id.clone(),
+                party_a: short_code,
+                identifier_type: request.identifier_type.code().to_string(),
+                result_url,
+                queue_timeout_url,
+                remarks: request.remarks.clone(),
+                occasion: request.occasion.clone(),
+            };
+
+            let (status, text) = self.post_authorized(&url, &request_body).await?;
+            let mut response: TransactionStatusResponse = parse_response(status, text)?;
+            response.pending = response
+                .originator_conversation_id
+                .clone()
+                .or_else(|| response.conversation_id.clone())
+                .map(|id| self.inner.callbacks.status.register(id));
+            Ok(response)
+        }
+
+        /// Reverses a completed transaction, e.g. a C2B payment made in error.
+        ///
+        /// Thin wrapper around [`MpesaClient::send_reversal`].
+        ///
+        /// # Arguments
+        /// * `initiator_name` - The initiator username.
+        /// * `security_credential` - Generated security credential.
+        /// * `transaction_id` - The M-Pesa receipt of the transaction to reverse.
+        /// * `amount` - The amount to reverse in KES.
+        /// * `short_code` - Business shortcode that received the transaction.
+        /// * `remarks` - Request remarks.
+        /// * `result_url` - URL to receive the result callback.
+        /// * `queue_timeout_url` - URL for timeout notifications.
+        /// * `occasion` - Optional occasion description.
+        #[allow(clippy::too_many_arguments)]
+        pub async fn reverse_transaction(
+            &self,
+            initiator_name: &str,
+            security_credential: &str,
+            transaction_id: &str,
+            amount: u32,
+            short_code: &str,
+            remarks: &str,
+            result_url: &str,
+            queue_timeout_url: &str,
+            occasion: &str,
+        ) -> Result<ReversalResponse, MpesaError> {
+            let request = ReversalRequest::builder()
+                .initiator_name(initiator_name)
+                .security_credential(security_credential)
+                .transaction_id(transaction_id)
+                .amount(amount)
+                .short_code(short_code)
+                .remarks(remarks)
+                .result_url(result_url)
+                .queue_timeout_url(queue_timeout_url)
+                .occasion(occasion)
+                .build()?;
+            self.send_reversal(&request).await
+        }
+
+        /// Reverses a completed transaction, e.g. a C2B payment made in error.
+        pub async fn send_reversal(
+            &self,
+            request: &ReversalRequest,
+        ) -> Result<ReversalResponse, MpesaError> {
+            let InitiatorFields {
+                initiator_name,
+                security_credential,
+                short_code,
+                result_url,
+                queue_timeout_url,
+            } = self.resolve_initiator(
+                &request.initiator_name,
+                &request.security_credential,
+                &request.short_code,
+                &request.result_url,
+                &request.queue_timeout_url,
+            )?;
+
+            let url = self.url("/mpesa/reversal/v1/request");
+
+            let request_body = ReversalPayload {
+                initiator: initiator_name,
+                security_credential,
+                command_id: "TransactionReversal".to_string(),
+                transaction_id: request.transaction_id.clone(),
+                amount: request.amount.to_string(),
+                receiver_party: short_code,
+                receiver_identifier_type: "11".to_string(),
+                result_url,
+                queue_timeout_url,
+                remarks: request.remarks.clone(),
+                occasion: request.occasion.clone(),
+            };
+
+            let (status, text) = self.post_authorized(&url, &request_body).await?;
+            parse_response(status, text)
+        }
+    }
+
+    /// Background task that settles STK Pushes whose callback never arrived.
+    ///
+    /// Every `interval` it reads the pending STK Pushes from the client's
+    /// [`TransactionStore`] and queries those older than `min_age` with
+    /// [`MpesaClient::stk_push_query`]. A final result is turned into a
+    /// [`reconciled`](StkCallback::reconciled) [`StkCallback`], passed to
+    /// [`MpesaClient::complete_stk_push`] and then to the handler, just like a real
+    /// callback. Query results carry no receipt number or transaction date, so a
+    /// paid push comes out as [`StkOutcome::Confirmed`]. Queries that fail or find
+    /// the push still in progress are retried with doubling backoff, up to
+    /// `max_attempts` times; after that the push is marked
+    /// [`TransactionStatus::Unresolved`] for manual review and no longer queried.
+    ///
+    /// The client's store must keep records (see [`TransactionStore::PERSISTS`]),
+    /// preferably durably like `sqlite::SqliteStore`; with [`NoStore`] there is
+    /// nothing to reconcile and [`StkReconciler::reconcile`] fails. Attempt counts
+    /// short of `max_attempts` are kept in memory and start over when the process
+    /// restarts. The passkey is taken from the client's [`BusinessProfile`]. The client already records the callback
+    /// and resolves the request waiting on it, so the handler should not be the
+    /// client itself.
+    ///
+    /// # Examples
+    /// ```no_run
+    /// use std::time::Duration;
+    /// use mpesa_daraja::mpesa::{
+    ///     BusinessProfile, CallbackHandler, MemoryStore, MpesaClient, StkCallback, StkReconciler,
+    /// };
+    ///
+    /// struct Orders;
+    ///
+    /// impl CallbackHandler for Orders {
+    ///     async fn on_stk_callback(&self, callback: StkCallback) {
+    ///         println!("{}: {}", callback.checkout_request_id, callback.result_desc);
+    ///     }
+    /// }
+    ///
+    /// # #[tokio::main]
+    /// # async fn main() {
+    /// let client = MpesaClient::builder("consumer_key", "consumer_secret")
+    ///     .profile(BusinessProfile::default().short_code("174379").passkey("passkey"))
+    ///     .store(MemoryStore::default())
+    ///     .build();
+    ///
+    /// let worker = StkReconciler::new(client, Orders)
+    ///     .min_age(Duration::from_secs(120))
+    ///     .spawn();
+    /// # }
+    /// ```
+    pub struct StkReconciler<T, S, H> {
+        client: MpesaClient<T, S>,
+        handler: H,
+        min_age: Duration,
+        interval: Duration,
+        retries: RetrySchedule,
+    }
+
+    /// Attempts made to settle each pending request, with doubling backoff.
+    struct RetrySchedule {
+        backoff: Duration,
+        max_attempts: u32,
+        attempts: HashMap<String, Attempts>,
+    }
+
+    struct Attempts {
+        count: u32,
+        next_at: Instant,
+    }
+
+    impl RetrySchedule {
+        fn new(backoff: Duration, max_attempts: u32) -> Self {
+            RetrySchedule {
+                backoff,
+                max_attempts,
+                attempts: HashMap::new(),
+            }
+        }
+
+        /// Forgets requests that are no longer pending.
+        fn retain(&mut self, pending: &[TransactionRecord]) {
+            self.attempts
+                .retain(|id, _| pending.iter().any(|record| &record.request_id == id));
+        }
+
+        /// Whether `id` may be attempted now: it has attempts left and its
+        /// backoff has elapsed.
+        fn is_due(&self, id: &str, now: Instant) -> bool {
+            match self.attempts.get(id) {
+                Some(attempts) => attempts.count < self.max_attempts && attempts.next_at <= now,
+                None => true,
+            }
+        }
+
+        fn succeeded(&mut self, id: &str) {
+            self.attempts.remove(id);
+        }
+
+        /// Schedules the next attempt, returning `false` once `id` has run out.
+        fn failed(&mut self, id: &str, now: Instant) -> bool {
+            let count = self.attempts.get(id).map_or(1, |attempts| attempts.count + 1);
+            let delay = self.backoff.saturating_mul(2u32.saturating_pow(count - 1));
+            self.attempts.insert(
+                id.to_string(),
+                Attempts {
+                    count,
+                    next_at: now + delay,
+                },
+            );
+            count < self.max_attempts
+        }
+    }
+
+    /// Marks a request that ran out of attempts as
+    /// [`TransactionStatus::Unresolved`], unless it was settled meanwhile, so it is
+    /// not picked up again after a restart.
+    async fn give_up<S: TransactionStore>(
+        store: &S,
+        id: &str,
+        attempts: u32,
+    ) -> Result<(), MpesaError> {
+        let update = TransactionUpdate {
+            status: TransactionStatus::Unresolved,
+            result_code: None,
+            result_desc: Some(format!("no final result after {} attempts", attempts)),
+            receipt: None,
+        };
+        store.update_if_pending(id, update).await?;
+        Ok(())
+    }
+
+    impl<T: Transport, S: TransactionStore, H: CallbackHandler> StkReconciler<T, S, H> {
+        /// Creates a reconciler for the STK Pushes recorded by `client`, reporting
+        /// results to `handler`.
+        pub fn new(client: MpesaClient<T, S>, handler: H) -> Self {
+            StkReconciler {
+                client,
+                handler,
+                min_age: Duration::from_secs(60),
+                interval: Duration::from_secs(30),
+                retries: RetrySchedule::new(Duration::from_secs(30), 5),
+            }
+        }
+
+        /// How long a push may wait for its callback before it is queried; defaults
+        /// to 60 seconds.
+        pub fn min_age(mut self, min_age: Duration) -> Self {
+            self.min_age = min_age;
+            self
+        }
+
+        /// How often the store is scanned; defaults to 30 seconds.
+        pub fn interval(mut self, interval: Duration) -> Self {
+            self.interval = interval;
+            self
+        }
+
+        /// Delay before the second query of a push, doubled after each further
+        /// attempt; defaults to 30 seconds.
+        pub fn backoff(mut self, backoff: Duration) -> Self {
+            self.retries.backoff = backoff;
+            self
+        }
+
+        /// How many times a push is queried before it is given up on; defaults to 5.
+        pub fn max_attempts(mut self, max_attempts: u32) -> Self {
+            self.retries.max_attempts = max_attempts;
+            self
+        }
+
+        /// Runs the reconciler on the tokio runtime until the handle is aborted.
+        pub fn spawn(self) -> tokio::task::JoinHandle<()> {
+            tokio::spawn(self.run())
+        }
+
+        /// Scans the store every `interval`, forever. Returns at once if the client
+        /// has no store.
+        pub async fn run(mut self) {
+            if !S::PERSISTS {
+                tracing::error!("STK reconciliation needs a transaction store; stopping");
+                return;
+            }
+            let mut ticker = tokio::time::interval(self.interval);
+            ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+            loop {
+                ticker.tick().await;
+                if let Err(err) = self.reconcile().await {
+                    tracing::error!("STK reconciliation failed: {}", err);
+                }
+            }
+        }
+
+        /// Queries every pending push that is due once.
+        ///
+        /// Returns [`MpesaError::Config`] if the client's store keeps no records, and
+        /// [`MpesaError::InvalidRequest`] if its profile has no passkey.
+        pub async fn reconcile(&mut self) -> Result<(), MpesaError> {
+            if !S::PERSISTS {
+                return Err(MpesaError::Config(
+                    "STK reconciliation needs a transaction store that persists records".to_string(),
+                ));
+            }
+            let pending = self.client.store().pending(TransactionKind::StkPush).await?;
+            self.retries.retain(&pending);
+
+            let now = Instant::now();
+            let cutoff = Utc::now() - self.min_age;
+            for record in pending.iter().filter(|record| record.created_at <= cutoff) {
+                if !self.retries.is_due(&record.request_id, now) {
+                    continue;
+                }
+                if self.query(record).await? {
+                    self.retries.succeeded(&record.request_id);
+                } else if !self.retries.failed(&record.request_id, now) {
+                    tracing::warn!("Giving up on STK Push {}", record.request_id);
+                    let attempts = self.retries.max_attempts;
+                    give_up(self.client.store(), &record.request_id, attempts).await?;
+                }
+            }
+            Ok(())
+        }
+
+        /// Queries one push, returning whether it reached a final result. Fails only
+        /// when the query cannot be made at all, e.g. without a passkey.
+        async fn query(&self, record: &TransactionRecord) -> Result<bool, MpesaError> {
+            let response = match self
+                .client
+                .stk_push_query(&record.request_id, Some(&record.short_code), None)
+                .await
+            {
+                Ok(response) => response,
+                Err(err @ MpesaError::InvalidRequest(_)) => return Err(err),
+                Err(err) => {
+                    // 500.001.1001: the customer has not responded yet.
+                    tracing::info!("STK Push {} not settled: {}", record.request_id, err);
+                    return Ok(false);
+                }
+            };
+            let Ok(result_code) = response.result_code.trim().parse::<i32>() else {
+                tracing::warn!(
+                    "STK Push {} queried with unknown ResultCode {:?}",
+                    record.request_id,
+                    response.result_code
+                );
+                return Ok(false);
+            };
+
+            let callback = StkCallback {
+                merchant_request_id: response
+                    .merchant_request_id
+                    .or_else(|| record.conversation_id.clone())
+                    .unwrap_or_default(),
+                checkout_request_id: record.request_id.clone(),
+                result_code,
+                result_desc: response.result_desc,
+                callback_metadata: None,
+                reconciled: true,
+            };
+            match self.client.complete_stk_push(&callback).await {
+                Ok(true) => self.handler.on_stk_callback(callback).await,
+                Ok(false) => tracing::info!("STK Push {} settled while being queried", record.request_id),
+                Err(err) => {
+                    tracing::error!("Failed to complete STK Push {}: {}", record.request_id, err);
+                    return Ok(false);
+                }
+            }
+            Ok(true)
+        }
+    }
+
+    /// Background task that settles B2C payments whose result never arrived, e.g.
+    /// after Daraja gave up on them and posted to the `QueueTimeOutURL`.
+    ///
+    /// Every `interval` it reads the pending B2C payments from the client's
+    /// [`TransactionStore`] and, for each one older than `min_age`, sends a
+    /// transaction status query on the payment's `OriginatorConversationID`. The
+    /// queries are sent concurrently, at most `max_concurrent_queries` at a time,
+    /// and their results awaited together, for up to `result_timeout` in all. A `TransactionStatus` of `Completed` marks the payment
+    /// [`TransactionStatus::Completed`] with its receipt, while `Failed`,
+    /// `Cancelled`, `Declined` or `Expired` mark it [`TransactionStatus::Failed`];
+    /// the updated record is passed to [`CallbackHandler::on_b2c_recovered`]. A
+    /// payment whose real result arrived while it was being queried is left as that
+    /// result recorded it, and the handler is not called.
+    ///
+    /// A failed query, a result that does not arrive in time or a status that is not
+    /// final is retried with doubling backoff, up to `max_attempts` times; after that
+    /// the payment is marked [`TransactionStatus::Unresolved`] for manual review and
+    /// no longer queried. A payment is never marked failed unless Daraja reports it
+    /// so, as paying it again could pay twice. Attempt counts short of
+    /// `max_attempts` are kept in memory and start over when the process restarts.
+    ///
+    /// Status results only reach the waiting query once passed to the client, by
+    /// mounting it on `server::router` or calling
+    /// [`MpesaClient::complete_transaction_status`]. The initiator, security
+    /// credential and callback URLs of the queries come from the client's
+    /// [`BusinessProfile`]. As with [`StkReconciler`], the client's store must
+    /// keep records. A recovered payment does not resolve
+    /// [`B2cResponse::wait_for_result`], which runs until its own timeout.
+    pub struct B2cRecovery<T, S, H> {
+        client: MpesaClient<T, S>,
+        handler: H,
+        min_age: Duration,
+        interval: Duration,
+        result_timeout: Duration,
+        max_concurrent_queries: usize,
+        retries: RetrySchedule,
+    }
+
+    impl<T: Transport, S: TransactionStore, H: CallbackHandler> B2cRecovery<T, S, H> {
+        /// Creates a recovery task for the B2C payments recorded by `client`,
+        /// reporting settled payments to `handler`.
+        pub fn new(client: MpesaClient<T, S>, handler: H) -> Self {
+            B2cRecovery {
+                client,
+                handler,
+                min_age: Duration::from_secs(300),
+                interval: Duration::from_secs(60),
+                result_timeout: Duration::from_secs(60),
+                max_concurrent_queries: 5,
+                retries: RetrySchedule::new(Duration::from_secs(60), 5),
+            }
+        }
+
+        /// How long a payment may wait for its result before it is queried;
+        /// defaults to 5 minutes.
+        pub fn min_age(mut self, min_age: Duration) -> Self {
+            self.min_age = min_age;
+            self
+        }
+
+        /// How often the store is scanned; defaults to 60 seconds.
+        pub fn interval(mut self, interval: Duration) -> Self {
+            self.interval = interval;
+            self
+        }
+
+        /// How long to wait for the results of a round of status queries; defaults
+        /// to 60 seconds.
+        pub fn result_timeout(mut self, result_timeout: Duration) -> Self {
+            self.result_timeout = result_timeout;
+            self
+        }
+
+        /// How many status queries may be in flight to Daraja at once; defaults to
+        /// 5. Sent queries wait for their results without holding a slot.
+        pub fn max_concurrent_queries(mut self, max_concurrent_queries: usize) -> Self {
+            self.max_concurrent_queries = max_concurrent_queries.max(1);
+            self
+        }
+
+        /// Delay before the second query of a payment, doubled after each further
+        /// attempt; defaults to 60 seconds.
+        pub fn backoff(mut self, backoff: Duration) -> Self {
+            self.retries.backoff = backoff;
+            self
+        }
+
+        /// How many times a payment is queried before it is given up on; defaults
+        /// to 5.
+        pub fn max_attempts(mut self, max_attempts: u32) -> Self {
+            self.retries.max_attempts = max_attempts;
+            self
+        }
+
+        /// Runs the recovery on the tokio runtime until the handle is aborted.
+        pub fn spawn(self) -> tokio::task::JoinHandle<()> {
+            tokio::spawn(self.run())
+        }
+
+        /// Scans the store every `interval`, forever. Returns at once if the client
+        /// has no store.
+        pub async fn run(mut self) {
+            if !S::PERSISTS {
+                tracing::error!("B2C recovery needs a transaction store; stopping");
+                return;
+            }
+            let mut ticker = tokio::time::interval(self.interval);
+            ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+            loop {
+                ticker.tick().await;
+                if let Err(err) = self.recover().await {
+                    tracing::error!("B2C recovery failed: {}", err);
+                }
+            }
+        }
+
+        /// Queries every pending payment that is due once.
+        ///
+        /// Returns [`MpesaError::Config`] if the client's store keeps no records.
+        pub async fn recover(&mut self) -> Result<(), MpesaError> {
+            if !S::PERSISTS {
+                return Err(MpesaError::Config(
+                    "B2C recovery needs a transaction store that persists records".to_string(),
+                ));
+            }
+            let pending = self.client.store().pending(TransactionKind::B2c).await?;
+            self.retries.retain(&pending);
+
+            let now = Instant::now();
+            let cutoff = Utc::now() - self.min_age;
+            let due: Vec<&TransactionRecord> = pending
+                .iter()
+                .filter(|record| record.created_at <= cutoff)
+                .filter(|record| self.retries.is_due(&record.request_id, now))
+                .collect();
+
+            let deadline = now + self.result_timeout;
+            let permits = Arc::new(tokio::sync::Semaphore::new(self.max_concurrent_queries));
+            let mut queries = tokio::task::JoinSet::new();
+            for (index, record) in due.iter().enumerate() {
+                let client = self.client.clone();
+                let permits = permits.clone();
+                let request = TransactionStatusRequest::builder()
+                    .original_conversation_id(&record.request_id)
+                    .short_code(&record.short_code)
+                    .remarks("B2C recovery")
+                    .build();
+                queries.spawn(async move {
+                    let result = match request {
+                        Ok(request) => query_status(&client, &request, &permits, deadline).await,
+                        Err(err) => Err(err),
+                    };
+                    (index, result)
+                });
+            }
+
+            let mut results: Vec<Option<Result<TransactionStatusResult, MpesaError>>> =
+                due.iter().map(|_| None).collect();
+            while let Some(joined) = queries.join_next().await {
+                match joined {
+                    Ok((index, result)) => results[index] = Some(result),
+                    Err(err) => tracing::error!("B2C status query task failed: {}", err),
+                }
+            }
+
+            for (record, result) in due.into_iter().zip(results) {
+                let settled = match result {
+                    Some(Ok(result)) => self.settle(record, result).await.unwrap_or_else(|err| {
+                        tracing::warn!("Failed to settle B2C payment {}: {}", record.request_id, err);
+                        false
+                    }),
+                    Some(Err(err)) => {
+                        tracing::warn!("Failed to query B2C payment {}: {}", record.request_id, err);
+                        false
+                    }
+                    None => false,
+                };
+                if settled {
+                    self.retries.succeeded(&record.request_id);
+                } else if !self.retries.failed(&record.request_id, now) {
+                    tracing::warn!("Giving up on B2C payment {}", record.request_id);
+                    let attempts = self.retries.max_attempts;
+                    give_up(self.client.store(), &record.request_id, attempts).await?;
+                }
+            }
+            Ok(())
+        }
+
+        /// Applies the status query result of one payment, returning whether it
+        /// reached a final status.
+        async fn settle(
+            &self,
+            record: &TransactionRecord,
+            result: TransactionStatusResult,
+        ) -> Result<bool, MpesaError> {
+            if !result.is_successful() {
+                tracing::info!(
+                    "B2C payment {} not settled: {} {}",
+                    record.request_id,
+                    result.result_code,
+                    result.result_desc
+                );
+                return Ok(false);
+            }
+
+            let summary = result.summary()?;
+            let status = match summary.transaction_status.to_ascii_lowercase().as_str() {
+                "completed" => TransactionStatus::Completed,
+                "failed" | "cancelled" | "declined" | "expired" => TransactionStatus::Failed,
+                _ => {
+                    tracing::info!(
+                        "B2C payment {} is {}",
+                        record.request_id,
+                        summary.transaction_status
+                    );
+                    return Ok(false);
+                }
+            };
+            let completed = status == TransactionStatus::Completed;
+            let update = TransactionUpdate {
+                status,
+                result_code: completed.then_some(0),
+                result_desc: Some(summary.transaction_status),
+                receipt: completed.then_some(summary.receipt_no),
+            };
+
+            let store = self.client.store();
+            if !store.update_if_pending(&record.request_id, update).await? {
+                tracing::info!("B2C payment {} settled while being queried", record.request_id);
+                return Ok(true);
+            }
+            if let Some(record) = store.get(&record.request_id).await? {
+                self.handler.on_b2c_recovered(record).await;
+            }
+            Ok(true)
+        }
+    }
+
+    /// Sends a status query and waits for its result until `deadline`.
+    async fn query_status<T: Transport, S: TransactionStore>(
+        client: &MpesaClient<T, S>,
+        request: &TransactionStatusRequest,
+        permits: &tokio::sync::Semaphore,
+        deadline: Instant,
+    ) -> Result<TransactionStatusResult, MpesaError> {
+        let response = {
+            let _permit = permits.acquire().await.expect("query semaphore is never closed");
+            client.send_transaction_status_query(request).await?
+        };
+        response
+            .wait_for_result(deadline.saturating_duration_since(Instant::now()))
+            .await
+    }
+
+    /// Callback HTTP server, enabled with the `server` feature.
+    ///
+    /// [`router`](server::router) mounts one `POST` route per callback type, decodes each payload
+    /// into the matching model, hands it to a [`CallbackHandler`] and acknowledges
+    /// Daraja with `{"ResultCode":0,"ResultDesc":"Accepted"}`.
+    #[cfg(feature = "server")]
+    pub mod server {
+        use super::*;
+        use axum::body::Bytes;
+        use axum::extract::State;
+        use axum::http::StatusCode;
+        use axum::response::{IntoResponse, Response};
+        use axum::routing::post;
+        use axum::{Json, Router};
+        use serde_json::json;
+
+        pub use super::CallbackHandler;
+
+        /// Paths the callback routes are mounted on; match them to the URLs
+        /// passed to Daraja.
+        #[derive(Debug, Clone)]
+        pub struct CallbackPaths {
+            stk: String,
+            b2c_result: String,
+            b2c_timeout: String,
+            balance_result: String,
+            transaction_status_result: String,
+        }
+
+        impl Default for CallbackPaths {
+            fn default() -> Self {
+                CallbackPaths {
+                    stk: "/mpesa/stk/callback".to_string(),
+                    b2c_result: "/mpesa/b2c/result".to_string(),
+                    b2c_timeout: "/mpesa/b2c/timeout".to_string(),
+                    balance_result: "/mpesa/balance/result".to_string(),
+                    transaction_status_result: "/mpesa/status/result".to_string(),
+                }
+            }
+        }
+
+        impl CallbackPaths {
+            /// STK Push `CallBackURL` path; defaults to `/mpesa/stk/callback`.
+            pub fn stk(mut self, path: impl Into<String>) -> Self {
+                self.stk = path.into();
+                self
+            }
+
+            /// B2C `ResultURL` path; defaults to `/mpesa/b2c/result`.
+            pub fn b2c_result(mut self, path: impl Into<String>) -> Self {
+                self.b2c_result = path.into();
+                self
+            }
+
+            /// B2C `QueueTimeOutURL` path; defaults to `/mpesa/b2c/timeout`.
+            pub fn b2c_timeout(mut self, path: impl Into<String>) -> Self {
+                self.b2c_timeout = path.into();
+                self
+            }
+
+            /// Balance query `ResultURL` path; defaults to `/mpesa/balance/result`.
+            pub fn balance_result(mut self, path: impl Into<String>) -> Self {
+                self.balance_result = path.into();
+                self
+            }
+
+            /// Transaction status `ResultURL` path; defaults to `/mpesa/status/result`.
+            pub fn transaction_status_result(mut self, path: impl Into<String>) -> Self {
+                self.transaction_status_result = path.into();
+                self
+            }
+        }
+
+        /// Builds a router dispatching callbacks posted to `paths` to `handler`.
+        ///
+        /// Result payloads that cannot be decoded are answered with `400 Bad Request`
+        /// and never reach the handler. The router has no state of its own, so it can be
+        /// merged into an application router of any state type.
+        ///
+        /// # Examples
+        /// ```no_run
+        /// use mpesa_daraja::mpesa::StkCallback;
+        /// use mpesa_daraja::mpesa::server::{router, CallbackHandler, CallbackPaths};
+        ///
+        /// struct Checkout;
+        ///
+        /// impl CallbackHandler for Checkout {
+        ///     async fn on_stk_callback(&self, callback: StkCallback) {
+        ///         println!("{}: {}", callback.checkout_request_id, callback.result_desc);
+        ///     }
+        /// }
+        ///
+        /// # #[tokio::main]
+        /// # async fn main() -> std::io::Result<()> {
+        /// let app = router(Checkout, CallbackPaths::default().stk("/callbacks/stk"));
+        /// let listener = tokio::net::TcpListener::bind("0.0.0.0:8080").await?;
+        /// axum::serve(listener, app).await
+        /// # }
+        /// ```
+        pub fn router<H, S>(handler: H, paths: CallbackPaths) -> Router<S>
+        where
+            H: CallbackHandler,
+            S: Clone + Send + Sync + 'static,
+        {
+            Router::new()
+                .route(&paths.stk, post(stk_callback::<H>))
+                .route(&paths.b2c_result, post(b2c_result::<H>))
+                .route(&paths.b2c_timeout, post(b2c_timeout::<H>))
+                .route(&paths.balance_result, post(balance_result::<H>))
+                .route(
+                    &paths.transaction_status_result,
+                    post(transaction_status_result::<H>),
+                )
+                .with_state(Arc::new(handler))
+        }
+
+        async fn stk_callback<H: CallbackHandler>(
+            State(handler): State<Arc<H>>,
+            body: Bytes,
+        ) -> Response {
+            let Some(payload) = decode::<StkCallbackRequest>(&body) else {
+                return rejected();
+            };
+            handler.on_stk_callback(payload.body.stk_callback).await;
+            accepted()
+        }
+
+        async fn b2c_result<H: CallbackHandler>(
+            State(handler): State<Arc<H>>,
+            body: Bytes,
+        ) -> Response {
+            let Some(payload) = decode::<B2cResultCallback>(&body) else {
+                return rejected();
+            };
+            handler.on_b2c_result(payload.result).await;
+            accepted()
+        }
+
+        /// Timeout notifications have no documented body, so any payload is accepted
+        /// and passed on as raw JSON.
+        async fn b2c_timeout<H: CallbackHandler>(
+            State(handler): State<Arc<H>>,
+            body: Bytes,
+        ) -> Response {
+            let payload = serde_json::from_slice(&body).unwrap_or(serde_json::Value::Null);
+            handler.on_b2c_timeout(payload).await;
+            accepted()
+        }
+
+        async fn balance_result<H: CallbackHandler>(
+            State(handler): State<Arc<H>>,
+            body: Bytes,
+        ) -> Response {
+            let Some(payload) = decode::<BalanceResultCallback>(&body) else {
+                return rejected();
+            };
+            handler.on_balance_result(payload.result).await;
+            accepted()
+        }
+
+        async fn transaction_status_result<H: CallbackHandler>(
+            State(handler): State<Arc<H>>,
+            body: Bytes,
+        ) -> Response {
+            let Some(payload) = decode::<TransactionStatusResultCallback>(&body) else {
+                return rejected();
+            };
+            handler.on_transaction_status_result(payload.result).await;
+            accepted()
+        }
+
+        fn decode<P: DeserializeOwned>(body: &[u8]) -> Option<P> {
+            match serde_json::from_slice(body) {
+                Ok(payload) => Some(payload),
+                Err(err) => {
+                    tracing::warn!(
+                        "Rejected M-Pesa callback ({}): {}",
+                        err,
+                        String::from_utf8_lossy(body)
+                    );
+                    None
+                }
+            }
+        }
+
+        fn accepted() -> Response {
+            Json(json!({ "ResultCode": 0, "ResultDesc": "Accepted" })).into_response()
+        }
+
+        fn rejected() -> Response {
+            let reply = json!({ "ResultCode": 1, "ResultDesc": "Rejected" });
+            (StatusCode::BAD_REQUEST, Json(reply)).into_response()
+        }
+    }
+
+    /// SQLite-backed [`TransactionStore`], enabled with the `sqlite` feature.
+    #[cfg(feature = "sqlite")]
+    pub mod sqlite {
+        use super::*;
+        use chrono::SecondsFormat;
+        use rusqlite::{params, Connection, OptionalExtension, Row};
+
+        const SCHEMA: &str = "
+            CREATE TABLE IF NOT EXISTS mpesa_transactions (
+                request_id      TEXT PRIMARY KEY,
+                kind            TEXT NOT NULL,
+                conversation_id TEXT,
+                amount          INTEGER NOT NULL,
+                party           TEXT NOT NULL,
+                short_code      TEXT NOT NULL,
+                status          TEXT NOT NULL,
+                result_code     INTEGER,
+                result_desc     TEXT,
+                receipt         TEXT,
+                created_at      TEXT NOT NULL,
+                updated_at      TEXT NOT NULL
+            );
+            CREATE INDEX IF NOT EXISTS mpesa_transactions_pending
+                ON mpesa_transactions (kind, status, created_at);
+        ";
+
+        const COLUMNS: &str = "request_id, kind, conversation_id, amount, party, short_code, \
+            status, result_code, result_desc, receipt, created_at, updated_at";
+
+        /// A [`TransactionStore`] in an SQLite database, kept in the
+        /// `mpesa_transactions` table. Clones share the same connection.
+        #[derive(Debug, Clone)]
+        pub struct SqliteStore {
+            conn: Arc<std::sync::Mutex<Connection>>,
+        }
+
+        impl SqliteStore {
+            /// Opens or creates the database at `path`, creating the table if needed.
+            pub fn open(path: impl AsRef<Path>) -> Result<Self, MpesaError> {
+                Self::with_connection(Connection::open(path).map_err(store_error)?)
+            }
+
+            /// Opens a private in-memory database.
+            pub fn open_in_memory() -> Result<Self, MpesaError> {
+                Self::with_connection(Connection::open_in_memory().map_err(store_error)?)
+            }
+
+            /// Uses an already open connection, creating the table if needed.
+            pub fn with_connection(conn: Connection) -> Result<Self, MpesaError> {
+                conn.execute_batch(SCHEMA).map_err(store_error)?;
+                Ok(SqliteStore {
+                    conn: Arc::new(std::sync::Mutex::new(conn)),
+                })
+            }
+
+            async fn apply(
+                &self,
+                request_id: &str,
+                update: TransactionUpdate,
+                pending_only: bool,
+            ) -> Result<bool, MpesaError> {
+                let request_id = request_id.to_string();
+                let condition = if pending_only { " AND status = 'pending'" } else { "" };
+                self.run(move |conn| {
+                    conn.execute(
+                        &format!(
+                            "UPDATE mpesa_transactions \
+                             SET status = ?2, result_code = ?3, result_desc = ?4, receipt = ?5, updated_at = ?6 \
+                             WHERE request_id = ?1{}",
+                            condition
+                        ),
+                        params![
+                            request_id,
+                            update.status.as_str(),
+                            update.result_code,
+                            update.result_desc,
+                            update.receipt,
+                            timestamp(&Utc::now()),
+                        ],
+                    )
+                    .map(|changed| changed > 0)
+                })
+                .await
+            }
+
+            /// Runs `f` with the connection on the blocking thread pool.
+            async fn run<R, F>(&self, f: F) -> Result<R, MpesaError>
+            where
+                R: Send + 'static,
+                F: FnOnce(&Connection) -> rusqlite::Result<R> + Send + 'static,
+            {
+                let conn = Arc::clone(&self.conn);
+                tokio::task::spawn_blocking(move || {
+                    let conn = conn.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+                    f(&conn).map_err(store_error)
+                })
+                .await
+                .map_err(|err| MpesaError::Store(err.into()))?
+            }
+        }
+
+        impl TransactionStore for SqliteStore {
+            async fn insert(&self, record: TransactionRecord) -> Result<(), MpesaError> {
+                self.run(move |conn| {
+                    conn.execute(
+                        &format!(
+                            "INSERT OR REPLACE INTO mpesa_transactions ({}) \
+                             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12)",
+                            COLUMNS
+                        ),
+                        params![
+                            record.request_id,
+                            record.kind.as_str(),
+                            record.conversation_id,
+                            record.amount,
+                            record.party,
+                            record.short_code,
+                            record.status.as_str(),
+                            record.result_code,
+                            record.result_desc,
+                            record.receipt,
+                            timestamp(&record.created_at),
+                            timestamp(&record.updated_at),
+                        ],
+                    )
+                    .map(|_| ())
+                })
+                .await
+            }
+
+            async fn update(&self, request_id: &str, update: TransactionUpdate) -> Result<bool, MpesaError> {
+                self.apply(request_id, update, false).await
+            }
+
+            async fn update_if_pending(
+                &self,
+                request_id: &str,
+                update: TransactionUpdate,
+            ) -> Result<bool, MpesaError> {
+                self.apply(request_id, update, true).await
+            }
+
+            async fn get(&self, request_id: &str) -> Result<Option<TransactionRecord>, MpesaError> {
+                let request_id = request_id.to_string();
+                let row = self
+                    .run(move |conn| {
+                        conn.query_row(
+                            &format!("SELECT {} FROM mpesa_transactions WHERE request_id = ?1", COLUMNS),
+                            params![request_id],
+                            RawRecord::from_row,
+                        )
+                        .optional()
+                    })
+                    .await?;
+                row.map(RawRecord::into_record).transpose()
+            }
+
+            async fn pending(&self, kind: TransactionKind) -> Result<Vec<TransactionRecord>, MpesaError> {
+                let rows = self
+                    .run(move |conn| {
+                        let mut statement = conn.prepare(&format!(
+                            "SELECT {} FROM mpesa_transactions \
+                             WHERE kind = ?1 AND status = ?2 ORDER BY created_at",
+                            COLUMNS
+                        ))?;
+                        let rows = statement.query_map(
+                            params![kind.as_str(), TransactionStatus::Pending.as_str()],
+                            RawRecord::from_row,
+                        )?;
+                        rows.collect::<rusqlite::Result<Vec<_>>>()
+                    })
+                    .await?;
+                rows.into_iter().map(RawRecord::into_record).collect()
+            }
+        }
+
+        /// A row as stored, before its enums and timestamps are parsed.
+        struct RawRecord {
+            request_id: String,
+            kind: String,
+            conversation_id: Option<String>,
+            amount: u32,
+            party: String,
+            short_code: String,
+            status: String,
+            result_code: Option<i32>,
+            result_desc: Option<String>,
+            receipt: Option<String>,
+            created_at: String,
+            updated_at: String,
+        }
+
+        impl RawRecord {
+            fn from_row(row: &Row<'_>) -> rusqlite::Result<Self> {
+                Ok(RawRecord {
+                    request_id: row.get(0)?,
+                    kind: row.get(1)?,
+                    conversation_id: row.get(2)?,
+                    amount: row.get(3)?,
+                    party: row.get(4)?,
+                    short_code: row.get(5)?,
+                    status: row.get(6)?,
+                    result_code: row.get(7)?,
+                    result_desc: row.get(8)?,
+                    receipt: row.get(9)?,
+                    created_at: row.get(10)?,
+                    updated_at: row.get(11)?,
+                })
+            }
+
+            fn into_record(self) -> Result<TransactionRecord, MpesaError> {
+                Ok(TransactionRecord {
+                    kind: self.kind.parse()?,
+                    request_id: self.request_id,
+                    conversation_id: self.conversation_id,
+                    amount: self.amount,
+                    party: self.party,
+                    short_code: self.short_code,
+                    status: self.status.parse()?,
+                    result_code: self.result_code,
+                    result_desc: self.result_desc,
+                    receipt: self.receipt,
+                    created_at: parse_timestamp(&self.created_at)?,
+                    updated_at: parse_timestamp(&self.updated_at)?,
+                })
+            }
+        }
+
+        /// Fixed-width RFC 3339, so timestamps sort correctly as text.
+        fn timestamp(value: &DateTime<Utc>) -> String {
+            value.to_rfc3339_opts(SecondsFormat::Micros, true)
+        }
+
+        fn parse_timestamp(value: &str) -> Result<DateTime<Utc>, MpesaError> {
+            DateTime::parse_from_rfc3339(value)
+                .map(|timestamp| timestamp.with_timezone(&Utc))
+                .map_err(|err| MpesaError::Store(err.into()))
+        }
+
+        fn store_error(err: rusqlite::Error) -> MpesaError {
+            MpesaError::Store(err.into())
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use serde_json::json;
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        type Respond = dyn Fn(&HttpRequest) -> (StatusCode, serde_json::Value) + Send + Sync;
+
+        /// In-process Daraja: issues `token-1`, `token-2`, ... from `/oauth/` and
+        /// answers every other request with `respond`.
+        #[derive(Clone)]
+        struct FakeDaraja {
+            sent: Arc<std::sync::Mutex<Vec<HttpRequest>>>,
+            tokens: Arc<AtomicUsize>,
+            respond: Arc<Respond>,
+        }
+
+        impl FakeDaraja {
+            fn new(
+                respond: impl Fn(&HttpRequest) -> (StatusCode, serde_json::Value) + Send + Sync + 'static,
+            ) -> Self {
+                FakeDaraja {
+                    sent: Arc::default(),
+                    tokens: Arc::default(),
+                    respond: Arc::new(respond),
+                }
+            }
+
+            /// Requests sent so far whose URL contains `path`.
+            fn sent(&self, path: &str) -> Vec<HttpRequest> {
+                let sent = self.sent.lock().unwrap();
+                sent.iter().filter(|request| request.url.contains(path)).cloned().collect()
+            }
+        }
+
+        impl Transport for FakeDaraja {
+            async fn send(&self, request: HttpRequest) -> Result<HttpResponse, MpesaError> {
+                let (status, body) = if request.url.contains("/oauth/") {
+                    // Give concurrent callers the chance to race for the token.
+                    tokio::task::yield_now().await;
+                    let token = self.tokens.fetch_add(1, Ordering::SeqCst) + 1;
+                    let body = json!({"access_token": format!("token-{}", token), "expires_in": "3599"});
+                    (StatusCode::OK, body)
+                } else {
+                    (self.respond)(&request)
+                };
+                self.sent.lock().unwrap().push(request);
+                Ok(HttpResponse {
+                    status,
+                    body: body.to_string(),
+                })
+            }
+        }
+
+        fn stk_push_accepted() -> (StatusCode, serde_json::Value) {
+            let body = json!({
+                "MerchantRequestID": "29115-34620561-1",
+                "CheckoutRequestID": "ws_CO_1",
+                "ResponseCode": "0",
+                "ResponseDescription": "Success",
+                "CustomerMessage": "Success"
+            });
+            (StatusCode::OK, body)
+        }
+
+        async fn stk_push<T: Transport, S: TransactionStore>(
+            client: &MpesaClient<T, S>,
+        ) -> Result<StkPushResponse, MpesaError> {
+            client
+                .stk_push("254712345678", 10, "INV-1", "Invoice", "https://example.com/cb", "174379", "passkey")
+                .await
+        }
+
+        #[test]
+        fn debug_output_redacts_secrets() {
+            let builder = MpesaClient::builder("key", "consumer-secret").profile(
+                BusinessProfile::default()
+                    .short_code("174379")
+                    .passkey("stk-passkey")
+                    .security_credential("encrypted-credential"),
+            );
+            let printed = format!("{:?}", builder);
+            assert!(printed.contains("174379"), "{}", printed);
+            for secret in ["consumer-secret", "stk-passkey", "encrypted-credential"] {
+                assert!(!printed.contains(secret), "{} leaked in {}", secret, printed);
+            }
+        }
+
+        #[test]
+        fn config_debug_output_redacts_secrets() {
+            let config = MpesaConfig {
+                consumer_key: Some("key".to_string()),
+                consumer_secret: Some("consumer-secret".to_string()),
+                passkey: Some("stk-passkey".to_string()),
+                initiator_password: Some("initiator-password".to_string()),
+                security_credential: Some("encrypted-credential".to_string()),
+                ..MpesaConfig::default()
+            };
+            let printed = format!("{:?}", config);
+            assert!(printed.contains("key"), "{}", printed);
+            for secret in ["consumer-secret", "stk-passkey", "initiator-password", "encrypted-credential"] {
+                assert!(!printed.contains(secret), "{} leaked in {}", secret, printed);
+            }
+        }
+
+        #[tokio::test]
+        async fn reqwest_transport_reports_failures_as_transport_errors() {
+            let request = HttpRequest {
+                method: Method::GET,
+                url: "not a url".to_string(),
+                authorization: "Basic a2V5OnNlY3JldA==".to_string(),
+                body: None,
+            };
+            let result = ReqwestTransport::default().send(request).await;
+            assert!(matches!(result, Err(MpesaError::Transport(_))), "{:?}", result);
+        }
+
+        #[test]
+        fn result_parameters_accept_a_single_parameter() {
+            let parameters: ResultParameters =
+                serde_json::from_value(json!({"ResultParameter": {"Key": "ReceiptNo", "Value": "NLJ41HAY6Q"}}))
+                    .unwrap();
+            assert_eq!(parameters.get("ReceiptNo").map(ToString::to_string).as_deref(), Some("NLJ41HAY6Q"));
+        }
+
+        #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+        async fn concurrent_requests_share_one_token_fetch() {
+            let daraja = FakeDaraja::new(|_| stk_push_accepted());
+            let client = MpesaClient::builder("key", "secret").transport(daraja.clone()).build();
+
+            let mut pushes = tokio::task::JoinSet::new();
+            for _ in 0..16 {
+                let client = client.clone();
+                pushes.spawn(async move { stk_push(&client).await.map(|_| ()) });
+            }
+            while let Some(push) = pushes.join_next().await {
+                push.unwrap().unwrap();
+            }
+
+            assert_eq!(daraja.sent("/oauth/").len(), 1);
+            let pushes = daraja.sent("/stkpush/");
+            assert_eq!(pushes.len(), 16);
+            assert!(pushes.iter().all(|request| request.authorization == "Bearer token-1"));
+        }
+
+        #[tokio::test]
+        async fn unauthorized_response_refreshes_token_and_retries_once() {
+            let daraja = FakeDaraja::new(|request| {
+                if request.authorization == "Bearer token-1" {
+                    (StatusCode::UNAUTHORIZED, json!({"errorMessage": "Invalid Access Token"}))
+                } else {
+                    stk_push_accepted()
+                }
+            });
+            let client = MpesaClient::builder("key", "secret").transport(daraja.clone()).build();
+
+            let response = stk_push(&client).await.unwrap();
+            assert_eq!(response.checkout_request_id.as_deref(), Some("ws_CO_1"));
+            let authorizations: Vec<String> = daraja
+                .sent("/stkpush/")
+                .into_iter()
+                .map(|request| request.authorization)
+                .collect();
+            assert_eq!(authorizations, ["Bearer token-1", "Bearer token-2"]);
+
+            // The refreshed token is cached for later requests.
+            stk_push(&client).await.unwrap();
+            assert_eq!(daraja.sent("/oauth/").len(), 2);
+        }
+
+        #[tokio::test]
+        async fn second_unauthorized_response_is_an_auth_error() {
+            let daraja = FakeDaraja::new(|_| (StatusCode::UNAUTHORIZED, json!({})));
+            let client = MpesaClient::builder("key", "secret").transport(daraja.clone()).build();
+
+            let Err(err) = stk_push(&client).await else {
+                panic!("STK Push succeeded despite a second 401");
+            };
+            assert!(matches!(err, MpesaError::Auth { status: 401, .. }), "{:?}", err);
+            assert_eq!(daraja.sent("/stkpush/").len(), 2);
+        }
+
+        fn record(kind: TransactionKind, request_id: &str, created_at: DateTime<Utc>) -> TransactionRecord {
+            TransactionRecord {
+                kind,
+                request_id: request_id.to_string(),
+                conversation_id: Some(format!("conversation-{}", request_id)),
+                amount: 10,
+                party: "254712345678".to_string(),
+                short_code: "174379".to_string(),
+                status: TransactionStatus::Pending,
+                result_code: None,
+                result_desc: None,
+                receipt: None,
+                created_at,
+                updated_at: created_at,
+            }
+        }
+
+        fn request_ids(records: &[TransactionRecord]) -> Vec<&str> {
+            records.iter().map(|record| record.request_id.as_str()).collect()
+        }
+
+        /// Inserts, reads, orders and updates records the way the client does.
+        async fn check_store<S: TransactionStore>(store: &S) {
+            use chrono::TimeZone;
+            let whole_second = Utc.with_ymd_and_hms(2024, 1, 1, 12, 0, 0).unwrap();
+            // Sorts before `whole_second` when compared as unpadded RFC 3339 text.
+            let fractional = whole_second + chrono::Duration::microseconds(500_000);
+            let older = record(TransactionKind::StkPush, "ws_CO_1", whole_second);
+            let newer = record(TransactionKind::StkPush, "ws_CO_2", fractional);
+            let payment = record(TransactionKind::B2c, "AG_1", whole_second);
+            for record in [newer.clone(), older.clone(), payment.clone()] {
+                store.insert(record).await.unwrap();
+            }
+
+            assert_eq!(store.get("ws_CO_2").await.unwrap(), Some(newer));
+            assert_eq!(store.get("ws_CO_3").await.unwrap(), None);
+            let pending = store.pending(TransactionKind::StkPush).await.unwrap();
+            assert_eq!(request_ids(&pending), ["ws_CO_1", "ws_CO_2"]);
+            assert_eq!(store.pending(TransactionKind::B2c).await.unwrap(), [payment]);
+
+            let update = TransactionUpdate {
+                status: TransactionStatus::Completed,
+                result_code: Some(0),
+                result_desc: Some("Success".to_string()),
+                receipt: Some("NLJ7RT61SV".to_string()),
+            };
+            assert!(store.update("ws_CO_1", update.clone()).await.unwrap());
+            assert!(!store.update("ws_CO_3", update).await.unwrap());
+
+            let completed = store.get("ws_CO_1").await.unwrap().unwrap();
+            assert_eq!(completed.status, TransactionStatus::Completed);
+            assert_eq!(completed.result_code, Some(0));
+            assert_eq!(completed.receipt.as_deref(), Some("NLJ7RT61SV"));
+            assert_eq!(completed.created_at, older.created_at);
+            assert!(completed.updated_at > older.updated_at);
+            let pending = store.pending(TransactionKind::StkPush).await.unwrap();
+            assert_eq!(request_ids(&pending), ["ws_CO_2"]);
+
+            let queried = TransactionUpdate {
+                status: TransactionStatus::Completed,
+                result_code: Some(0),
+                result_desc: Some("Queried".to_string()),
+                receipt: None,
+            };
+            assert!(!store.update_if_pending("ws_CO_1", queried.clone()).await.unwrap());
+            assert_eq!(store.get("ws_CO_1").await.unwrap(), Some(completed));
+            assert!(store.update_if_pending("ws_CO_2", queried).await.unwrap());
+            assert!(store.pending(TransactionKind::StkPush).await.unwrap().is_empty());
+        }
+
+        #[tokio::test]
+        async fn memory_store_round_trips_records() {
+            check_store(&MemoryStore::default()).await;
+        }
+
+        #[cfg(feature = "sqlite")]
+        #[tokio::test]
+        async fn sqlite_store_round_trips_records() {
+            check_store(&sqlite::SqliteStore::open_in_memory().unwrap()).await;
+        }
+
+        #[cfg(feature = "sqlite")]
+        #[tokio::test]
+        async fn sqlite_store_keeps_records_across_reopen() {
+            let path = std::env::temp_dir().join(format!("mpesa-store-{}.db", std::process::id()));
+            let pending = record(TransactionKind::B2c, "AG_1", Utc::now());
+            sqlite::SqliteStore::open(&path).unwrap().insert(pending.clone()).await.unwrap();
+
+            let reopened = sqlite::SqliteStore::open(&path).unwrap();
+            let records = reopened.pending(TransactionKind::B2c).await.unwrap();
+            std::fs::remove_file(&path).unwrap();
+            assert_eq!(request_ids(&records), ["AG_1"]);
+        }
+
+        #[tokio::test]
+        async fn stk_push_is_recorded_and_completed_by_its_callback() {
+            let daraja = FakeDaraja::new(|_| stk_push_accepted());
+            let client = MpesaClient::builder("key", "secret")
+                .transport(daraja)
+                .store(MemoryStore::default())
+                .build();
+
+            stk_push(&client).await.unwrap();
+            let pending = client.store().pending(TransactionKind::StkPush).await.unwrap();
+            assert_eq!(request_ids(&pending), ["ws_CO_1"]);
+            assert_eq!(pending[0].conversation_id.as_deref(), Some("29115-34620561-1"));
+
+            let callback: StkCallbackRequest = serde_json::from_value(json!({"Body": {"stkCallback": {
+                "MerchantRequestID": "29115-34620561-1",
+                "CheckoutRequestID": "ws_CO_1",
+                "ResultCode": 1032,
+                "ResultDesc": "Request cancelled by user"
+            }}}))
+            .unwrap();
+            client.complete_stk_push(&callback.body.stk_callback).await.unwrap();
+
+            let record = client.store().get("ws_CO_1").await.unwrap().unwrap();
+            assert_eq!(record.status, TransactionStatus::Failed);
+            assert_eq!(record.result_code, Some(1032));
+            assert!(client.store().pending(TransactionKind::StkPush).await.unwrap().is_empty());
+        }
+
+        fn stk_callback(checkout_request_id: &str) -> StkCallback {
+            serde_json::from_value(json!({
+                "MerchantRequestID": "29115-34620561-1",
+                "CheckoutRequestID": checkout_request_id,
+                "ResultCode": 1032,
+                "ResultDesc": "Request cancelled by user"
+            }))
+            .unwrap()
+        }
+
+        #[tokio::test]
+        async fn stk_push_waiter_receives_its_callback() {
+            let client = MpesaClient::builder("key", "secret")
+                .transport(FakeDaraja::new(|_| stk_push_accepted()))
+                .build();
+            let response = stk_push(&client).await.unwrap();
+
+            assert!(!client.callbacks().resolve_stk(&stk_callback("ws_CO_2")));
+            assert!(client.callbacks().resolve_stk(&stk_callback("ws_CO_1")));
+            let callback = response.wait_for_result(Duration::from_secs(1)).await.unwrap();
+            assert_eq!(callback.checkout_request_id, "ws_CO_1");
+            assert_eq!(callback.result_code, 1032);
+        }
+
+        #[tokio::test]
+        async fn b2c_waiter_receives_its_result() {
+            let client = MpesaClient::builder("key", "secret")
+                .transport(FakeDaraja::new(|_| {
+                    let body = json!({
+                        "ConversationID": "AG_20191219_00005797af5d7d75f652",
+                        "OriginatorConversationID": "16740-34861180-1",
+                        "ResponseCode": "0",
+                        "ResponseDescription": "Accept the service request successfully."
+                    });
+                    (StatusCode::OK, body)
+                }))
+                .build();
+            let response = client
+                .business_payment(
+                    "254708374149",
+                    10,
+                    "Refund",
+                    "https://example.com/result",
+                    "https://example.com/timeout",
+                    "testapi",
+                    "credential",
+                    "600996",
+                    "",
+                )
+                .await
+                .unwrap();
+
+            let result: B2cResult = serde_json::from_value(json!({
+                "ResultType": 0,
+                "ResultCode": 2001,
+                "ResultDesc": "The initiator information is invalid.",
+                "OriginatorConversationID": "16740-34861180-1",
+                "ConversationID": "AG_20191219_00005797af5d7d75f652",
+                "TransactionID": "NLJ41HAY6Q"
+            }))
+            .unwrap();
+            let callbacks = client.callbacks().clone();
+            tokio::spawn(async move { assert!(callbacks.resolve_b2c(&result)) });
+            let result = response.wait_for_result(Duration::from_secs(1)).await.unwrap();
+            assert_eq!(result.result_code, 2001);
+            assert!(client.callbacks().b2c.lock().is_empty());
+        }
+
+        #[tokio::test]
+        async fn waiter_times_out_and_releases_its_slot() {
+            let client = MpesaClient::builder("key", "secret")
+                .transport(FakeDaraja::new(|_| stk_push_accepted()))
+                .build();
+            let response = stk_push(&client).await.unwrap();
+
+            let Err(err) = response.wait_for_result(Duration::from_millis(10)).await else {
+                panic!("no callback was delivered");
+            };
+            assert!(matches!(err, MpesaError::Timeout(ref id) if id == "ws_CO_1"), "{:?}", err);
+            assert!(client.callbacks().stk.lock().is_empty());
+            assert!(!client.callbacks().resolve_stk(&stk_callback("ws_CO_1")));
+        }
+
+        #[tokio::test]
+        async fn dropped_response_releases_its_slot() {
+            let client = MpesaClient::builder("key", "secret")
+                .transport(FakeDaraja::new(|_| stk_push_accepted()))
+                .build();
+            let response = stk_push(&client).await.unwrap();
+            assert_eq!(client.callbacks().stk.lock().len(), 1);
+
+            drop(response);
+            assert!(client.callbacks().stk.lock().is_empty());
+            assert!(!client.callbacks().resolve_stk(&stk_callback("ws_CO_1")));
+        }
+
+        /// Collects what a reconciler or a recovery hands to its handler.
+        #[derive(Clone, Default)]
+        struct Settled {
+            callbacks: Arc<std::sync::Mutex<Vec<StkCallback>>>,
+            records: Arc<std::sync::Mutex<Vec<TransactionRecord>>>,
+        }
+
+        impl Settled {
+            fn callbacks(&self) -> Vec<StkCallback> {
+                self.callbacks.lock().unwrap().clone()
+            }
+
+            fn records(&self) -> Vec<TransactionRecord> {
+                self.records.lock().unwrap().clone()
+            }
+        }
+
+        impl CallbackHandler for Settled {
+            async fn on_stk_callback(&self, callback: StkCallback) {
+                self.callbacks.lock().unwrap().push(callback);
+            }
+
+            async fn on_b2c_recovered(&self, record: TransactionRecord) {
+                self.records.lock().unwrap().push(record);
             }
         }
 
-        async fn get_access_token(&self) -> Result<String, Box<dyn Error>> {
-            let client = Client::new();
-            let auth = format!("{}:{}", self.consumer_key, self.consumer_secret);
-            let auth_encoded = general_purpose::STANDARD.encode(auth);
+        fn stk_push_in_progress() -> (StatusCode, serde_json::Value) {
+            let body = json!({
+                "requestId": "1",
+                "errorCode": "500.001.1001",
+                "errorMessage": "The transaction is being processed"
+            });
+            (StatusCode::INTERNAL_SERVER_ERROR, body)
+        }
 
-            let url = if self.environment == "sandbox" {
-                "https://sandbox.safaricom.co.ke/oauth/v1/generate?grant_type=client_credentials"
-            } else {
-                "https://api.safaricom.co.ke/oauth/v1/generate?grant_type=client_credentials"
-            };
+        fn stk_push_queried(result_code: &str, result_desc: &str) -> (StatusCode, serde_json::Value) {
+            let body = json!({
+                "MerchantRequestID": "29115-34620561-1",
+                "CheckoutRequestID": "ws_CO_1",
+                "ResponseCode": "0",
+                "ResponseDescription": "The service request has been accepted successfully",
+                "ResultCode": result_code,
+                "ResultDesc": result_desc
+            });
+            (StatusCode::OK, body)
+        }
 
-            let response = client
-                .get(url)
-                .header("Authorization", format!("Basic {}", auth_encoded))
-                .send()
-                .await?;
+        fn reconciling_client(daraja: FakeDaraja) -> MpesaClient<FakeDaraja, MemoryStore> {
+            MpesaClient::builder("key", "secret")
+                .profile(BusinessProfile::default().short_code("174379").passkey("passkey"))
+                .transport(daraja)
+                .store(MemoryStore::default())
+                .build()
+        }
+
+        #[test]
+        fn retry_schedule_doubles_backoff_until_out_of_attempts() {
+            let mut retries = RetrySchedule::new(Duration::from_secs(10), 3);
+            let start = Instant::now();
+            assert!(retries.is_due("ws_CO_1", start));
 
-            let token_data: AccessTokenResponse = response.json().await?;
-            Ok(token_data.access_token)
+            assert!(retries.failed("ws_CO_1", start));
+            assert!(!retries.is_due("ws_CO_1", start + Duration::from_secs(9)));
+            assert!(retries.is_due("ws_CO_1", start + Duration::from_secs(10)));
+
+            let second = start + Duration::from_secs(10);
+            assert!(retries.failed("ws_CO_1", second));
+            assert!(!retries.is_due("ws_CO_1", second + Duration::from_secs(19)));
+            assert!(retries.is_due("ws_CO_1", second + Duration::from_secs(20)));
+
+            let third = second + Duration::from_secs(20);
+            assert!(!retries.failed("ws_CO_1", third));
+            assert!(!retries.is_due("ws_CO_1", third + Duration::from_secs(3600)));
+
+            retries.succeeded("ws_CO_1");
+            assert!(retries.is_due("ws_CO_1", third));
+
+            retries.failed("ws_CO_2", third);
+            retries.retain(&[]);
+            assert!(retries.is_due("ws_CO_2", third));
         }
 
-        /// Initiates an STK Push (C2B) transaction, prompting the user to enter their PIN.
-        ///
-        /// # Arguments
-        /// * `phone_number` - The customer's phone number (e.g., "2547XXXXXXXX").
-        /// * `amount` - The amount to charge in KES.
-        /// * `account_reference` - A reference for the transaction (e.g., invoice number).
-        /// * `transaction_desc` - A description of the transaction.
-        /// * `callback_url` - URL to receive the transaction result.
-        /// * `short_code` - The business shortcode.
-        /// * `passkey` - The passkey from Safaricom.
-        pub async fn stk_push(
-            &self,
-            phone_number: &str,
-            amount: u32,
-            account_reference: &str,
-            transaction_desc: &str,
-            callback_url: &str,
-            short_code: &str,
-            passkey: &str,
-        ) -> Result<StkPushResponse, Box<dyn Error>> {
-            let client = Client::new();
-            let access_token = self.get_access_token().await?;
+        #[tokio::test]
+        async fn reconciler_settles_stale_pushes_once_they_are_final() {
+            let queries = Arc::new(AtomicUsize::new(0));
+            let counted = queries.clone();
+            let daraja = FakeDaraja::new(move |_| {
+                if counted.fetch_add(1, Ordering::SeqCst) == 0 {
+                    stk_push_in_progress()
+                } else {
+                    stk_push_queried("0", "The service request is processed successfully.")
+                }
+            });
+            let client = reconciling_client(daraja.clone());
+            let stale = record(TransactionKind::StkPush, "ws_CO_1", Utc::now() - chrono::Duration::minutes(2));
+            client.store().insert(stale).await.unwrap();
+            client.store().insert(record(TransactionKind::StkPush, "ws_CO_2", Utc::now())).await.unwrap();
 
-            let timestamp = Utc::now().format("%Y%m%d%H%M%S").to_string();
-            let password = general_purpose::STANDARD.encode(
-                format!("{}{}{}", short_code, passkey, timestamp)
-            );
+            let waiting = client.callbacks().stk.register("ws_CO_1".to_string());
 
-            let url = if self.environment == "sandbox" {
-                "https://sandbox.safaricom.co.ke/mpesa/stkpush/v1/processrequest"
-            } else {
-                "https://api.safaricom.co.ke/mpesa/stkpush/v1/processrequest"
+            let settled = Settled::default();
+            let mut reconciler = StkReconciler::new(client.clone(), settled.clone()).backoff(Duration::ZERO);
+
+            reconciler.reconcile().await.unwrap();
+            assert!(settled.callbacks().is_empty());
+            let pending = client.store().pending(TransactionKind::StkPush).await.unwrap();
+            assert_eq!(request_ids(&pending), ["ws_CO_1", "ws_CO_2"]);
+
+            reconciler.reconcile().await.unwrap();
+            let callbacks = settled.callbacks();
+            assert_eq!(callbacks.len(), 1);
+            assert!(callbacks[0].reconciled);
+            let Ok(StkOutcome::Confirmed(confirmation)) = callbacks[0].outcome() else {
+                panic!("reconciled payment is not confirmed");
             };
+            assert_eq!(confirmation.checkout_request_id, "ws_CO_1");
+            let resolved = waiting.wait(Duration::from_secs(1)).await.unwrap();
+            assert!(resolved.reconciled);
+            let record = client.store().get("ws_CO_1").await.unwrap().unwrap();
+            assert_eq!(record.status, TransactionStatus::Completed);
+            assert_eq!(record.result_code, Some(0));
 
-            let request_body = StkPushRequest {
-                business_short_code: short_code.to_string(),
-                password,
-                timestamp,
-                transaction_type: "CustomerPayBillOnline".to_string(),
-                amount: amount.to_string(),
-                party_a: phone_number.to_string(),
-                party_b: short_code.to_string(),
-                phone_number: phone_number.to_string(),
-                callback_url: callback_url.to_string(),
-                account_reference: account_reference.to_string(),
-                transaction_desc: transaction_desc.to_string(),
+            // Only the stale push was ever queried.
+            let queried = daraja.sent("/stkpushquery/");
+            assert_eq!(queried.len(), 2);
+            assert!(queried.iter().all(|request| request.body.as_deref().unwrap().contains("ws_CO_1")));
+        }
+
+        #[tokio::test]
+        async fn reconciler_records_failed_pushes_and_gives_up_on_unsettled_ones() {
+            let daraja = FakeDaraja::new(|request| {
+                if request.body.as_deref().unwrap().contains("ws_CO_1") {
+                    stk_push_queried("1032", "Request cancelled by user")
+                } else {
+                    stk_push_in_progress()
+                }
+            });
+            let client = reconciling_client(daraja.clone());
+            let stale = Utc::now() - chrono::Duration::minutes(2);
+            client.store().insert(record(TransactionKind::StkPush, "ws_CO_1", stale)).await.unwrap();
+            client.store().insert(record(TransactionKind::StkPush, "ws_CO_2", stale)).await.unwrap();
+
+            let settled = Settled::default();
+            let mut reconciler = StkReconciler::new(client.clone(), settled.clone())
+                .backoff(Duration::ZERO)
+                .max_attempts(2);
+            for _ in 0..4 {
+                reconciler.reconcile().await.unwrap();
+            }
+
+            let callbacks = settled.callbacks();
+            assert_eq!(callbacks.len(), 1);
+            let Ok(StkOutcome::Failed(failure)) = callbacks[0].outcome() else {
+                panic!("cancelled push is not a failure");
             };
+            assert_eq!(failure.reason, StkFailureReason::Cancelled);
+            let record = client.store().get("ws_CO_1").await.unwrap().unwrap();
+            assert_eq!(record.status, TransactionStatus::Failed);
+            assert_eq!(record.result_code, Some(1032));
+            let record = client.store().get("ws_CO_2").await.unwrap().unwrap();
+            assert_eq!(record.status, TransactionStatus::Unresolved);
+            let pending = client.store().pending(TransactionKind::StkPush).await.unwrap();
+            assert!(pending.is_empty());
+            assert_eq!(daraja.sent("/stkpushquery/").len(), 3);
 
-            let response = client
-                .post(url)
-                .header("Authorization", format!("Bearer {}", access_token))
-                .json(&request_body)
-                .send()
-                .await?;
+            // A restarted reconciler does not pick the abandoned push up again.
+            let mut restarted = StkReconciler::new(client.clone(), settled.clone());
+            restarted.reconcile().await.unwrap();
+            assert_eq!(daraja.sent("/stkpushquery/").len(), 3);
+        }
 
-            let text = response.text().await?;
+        #[tokio::test]
+        async fn reconciler_keeps_a_callback_that_lands_during_its_query() {
+            let store = MemoryStore::default();
+            let racing = store.clone();
+            let daraja = FakeDaraja::new(move |_| {
+                // The real callback is recorded while the query is in flight.
+                let update = TransactionUpdate {
+                    status: TransactionStatus::Completed,
+                    result_code: Some(0),
+                    result_desc: Some("The service request is processed successfully.".to_string()),
+                    receipt: Some("NLJ7RT61SV".to_string()),
+                };
+                racing.apply("ws_CO_1", update, false);
+                stk_push_queried("0", "The service request is processed successfully.")
+            });
+            let client = MpesaClient::builder("key", "secret")
+                .profile(BusinessProfile::default().short_code("174379").passkey("passkey"))
+                .transport(daraja)
+                .store(store)
+                .build();
+            let stale = Utc::now() - chrono::Duration::minutes(2);
+            client.store().insert(record(TransactionKind::StkPush, "ws_CO_1", stale)).await.unwrap();
 
-            // Log raw response (very useful for debugging)
-            tracing::info!("M-Pesa STK Push Raw Response: {}", text);
+            let settled = Settled::default();
+            StkReconciler::new(client.clone(), settled.clone()).reconcile().await.unwrap();
+
+            assert!(settled.callbacks().is_empty());
+            let record = client.store().get("ws_CO_1").await.unwrap().unwrap();
+            assert_eq!(record.receipt.as_deref(), Some("NLJ7RT61SV"));
+        }
+
+        #[tokio::test]
+        async fn reconciler_refuses_a_client_without_a_store() {
+            let client = MpesaClient::builder("key", "secret")
+                .profile(BusinessProfile::default().passkey("passkey"))
+                .transport(FakeDaraja::new(|_| stk_push_in_progress()))
+                .build();
+
+            let mut reconciler = StkReconciler::new(client, Settled::default());
+            assert!(matches!(reconciler.reconcile().await, Err(MpesaError::Config(_))));
+        }
+
+        /// Acknowledges a status query under an ID derived from the queried payment.
+        fn status_query_accepted(request: &HttpRequest) -> (StatusCode, serde_json::Value) {
+            let body = request.body.as_deref().unwrap();
+            let payload: serde_json::Value = serde_json::from_str(body).unwrap();
+            let queried = payload["OriginalConversationID"].as_str().unwrap();
+            let body = json!({
+                "OriginatorConversationID": format!("status-{}", queried),
+                "ConversationID": format!("AG_status-{}", queried),
+                "ResponseCode": "0",
+                "ResponseDescription": "Accept the service request successfully."
+            });
+            (StatusCode::OK, body)
+        }
+
+        fn status_result(queried: &str, transaction_status: &str) -> TransactionStatusResult {
+            serde_json::from_value(json!({
+                "ResultType": 0,
+                "ResultCode": "0",
+                "ResultDesc": "The service request is processed successfully.",
+                "OriginatorConversationID": format!("status-{}", queried),
+                "ConversationID": format!("AG_status-{}", queried),
+                "TransactionID": "MHK61H7L7Q",
+                "ResultParameters": {"ResultParameter": [
+                    {"Key": "DebitPartyName", "Value": "600310 - Safaricom333"},
+                    {"Key": "CreditPartyName", "Value": "254708374149 - John Doe"},
+                    {"Key": "TransactionStatus", "Value": transaction_status},
+                    {"Key": "Amount", "Value": 10},
+                    {"Key": "ReceiptNo", "Value": format!("receipt-{}", queried)}
+                ]}
+            }))
+            .unwrap()
+        }
 
-            // Try Success Response First
-            match serde_json::from_str::<StkPushResponse>(&text) {
-                Ok(stk) if stk.response_code == "0" => {
-                    return Ok(stk);
+        /// Posts `result` to the client as Daraja would, once its query is waiting.
+        fn deliver<T: Transport, S: TransactionStore>(
+            client: &MpesaClient<T, S>,
+            result: TransactionStatusResult,
+        ) {
+            let callbacks = client.callbacks().clone();
+            tokio::spawn(async move {
+                while !callbacks.resolve_transaction_status(&result) {
+                    tokio::time::sleep(Duration::from_millis(1)).await;
                 }
-                _ => {}
-            }
+            });
+        }
 
-            // Try to parse as error response
-            if let Ok(err) = serde_json::from_str::<ErrorResponse>(&text) {
-                return Err(format!(
-                    "M-Pesa Error {}: {} (Response ID: {})",
-                    err.error_code, 
-                    err.error_message, 
-                    err.response_id.unwrap_or_default()
-                ).into());
+        #[tokio::test]
+        async fn recovery_settles_payments_from_status_results_awaited_together() {
+            let client = MpesaClient::builder("key", "secret")
+                .profile(
+                    BusinessProfile::default()
+                        .short_code("600987")
+                        .initiator_name("testapi")
+                        .security_credential("credential")
+                        .result_url("https://example.com/result")
+                        .queue_timeout_url("https://example.com/timeout"),
+                )
+                .transport(FakeDaraja::new(status_query_accepted))
+                .store(MemoryStore::default())
+                .build();
+            let stale = Utc::now() - chrono::Duration::minutes(10);
+            for (minute, id) in ["AG_1", "AG_2", "AG_3", "AG_4"].into_iter().enumerate() {
+                let created_at = stale + chrono::Duration::minutes(minute as i64);
+                client.store().insert(record(TransactionKind::B2c, id, created_at)).await.unwrap();
             }
+            client.store().insert(record(TransactionKind::B2c, "AG_5", Utc::now())).await.unwrap();
+
+            deliver(&client, status_result("AG_1", "Completed"));
+            deliver(&client, status_result("AG_2", "Failed"));
+            let settled = Settled::default();
+            let result_timeout = Duration::from_millis(300);
+            let mut recovery =
+                B2cRecovery::new(client.clone(), settled.clone()).result_timeout(result_timeout);
 
-            // Fallback
-            Err(format!("Unknown M-Pesa response: {}", text).into())
+            // AG_3 and AG_4 never get a result; waiting on them one after the other
+            // would take twice the timeout.
+            let started = Instant::now();
+            recovery.recover().await.unwrap();
+            assert!(started.elapsed() < result_timeout * 2, "{:?}", started.elapsed());
+
+            let mut records = settled.records();
+            records.sort_by(|a, b| a.request_id.cmp(&b.request_id));
+            assert_eq!(request_ids(&records), ["AG_1", "AG_2"]);
+            assert_eq!(records[0].status, TransactionStatus::Completed);
+            assert_eq!(records[0].receipt.as_deref(), Some("receipt-AG_1"));
+            assert_eq!(records[1].status, TransactionStatus::Failed);
+            assert_eq!(records[1].receipt, None);
+            let pending = client.store().pending(TransactionKind::B2c).await.unwrap();
+            assert_eq!(request_ids(&pending), ["AG_3", "AG_4", "AG_5"]);
         }
 
-        /// Generates a security credential for B2C, balance, and transaction status APIs.
-        ///
-        /// # Arguments
-        /// * `initiator_password` - The initiator's password.
-        /// * `is_production` - Whether to use the production certificate.
-        /// * `cert_path` - Optional path to the certificate file; defaults to "certs/production.cer" or "certs/sandbox.cer".
-        pub fn generate_security_credential(
-            initiator_password: &str,
-            is_production: bool,
-            cert_path: Option<&str>,
-        ) -> Result<String, Box<dyn Error>> {
-            let default_path = if is_production {
-                "certs/production.cer"
-            } else {
-                "certs/sandbox.cer"
+        /// Daraja answering status queries slowly, tracking how many are in flight.
+        #[derive(Clone)]
+        struct SlowDaraja {
+            daraja: FakeDaraja,
+            in_flight: Arc<AtomicUsize>,
+            most_in_flight: Arc<AtomicUsize>,
+        }
+
+        impl Transport for SlowDaraja {
+            async fn send(&self, request: HttpRequest) -> Result<HttpResponse, MpesaError> {
+                if request.url.contains("/oauth/") {
+                    return self.daraja.send(request).await;
+                }
+                let in_flight = self.in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+                self.most_in_flight.fetch_max(in_flight, Ordering::SeqCst);
+                tokio::time::sleep(Duration::from_millis(20)).await;
+                self.in_flight.fetch_sub(1, Ordering::SeqCst);
+                self.daraja.send(request).await
+            }
+        }
+
+        #[tokio::test]
+        async fn recovery_bounds_the_status_queries_in_flight() {
+            let daraja = SlowDaraja {
+                daraja: FakeDaraja::new(status_query_accepted),
+                in_flight: Arc::default(),
+                most_in_flight: Arc::default(),
             };
-            let path = cert_path.unwrap_or(default_path);
+            let client = MpesaClient::builder("key", "secret")
+                .profile(
+                    BusinessProfile::default()
+                        .initiator_name("testapi")
+                        .security_credential("credential")
+                        .result_url("https://example.com/result")
+                        .queue_timeout_url("https://example.com/timeout"),
+                )
+                .transport(daraja.clone())
+                .store(MemoryStore::default())
+                .build();
+            let stale = Utc::now() - chrono::Duration::minutes(10);
+            for (minute, id) in ["AG_1", "AG_2", "AG_3", "AG_4", "AG_5"].into_iter().enumerate() {
+                let created_at = stale + chrono::Duration::minutes(minute as i64);
+                client.store().insert(record(TransactionKind::B2c, id, created_at)).await.unwrap();
+            }
 
-            let cert_content = fs::read(path)?;
-            let cert = X509::from_pem(&cert_content)?;
-            let public_key = cert.public_key()?;
-            let rsa = public_key.rsa()?;
+            let mut recovery = B2cRecovery::new(client.clone(), Settled::default())
+                .result_timeout(Duration::from_millis(200))
+                .max_concurrent_queries(2);
+            recovery.recover().await.unwrap();
 
-            let mut encrypted = vec![0; rsa.size() as usize];
-            let len = rsa.public_encrypt(
-                initiator_password.as_bytes(),
-                &mut encrypted,
-                Padding::PKCS1,
-            )?;
+            assert_eq!(daraja.daraja.sent("/transactionstatus/").len(), 5);
+            assert_eq!(daraja.most_in_flight.load(Ordering::SeqCst), 2);
+        }
 
-            Ok(general_purpose::STANDARD.encode(&encrypted[..len]))
+        #[tokio::test]
+        async fn recovery_leaves_payments_with_a_non_final_status_pending() {
+            let client = MpesaClient::builder("key", "secret")
+                .profile(
+                    BusinessProfile::default()
+                        .initiator_name("testapi")
+                        .security_credential("credential")
+                        .result_url("https://example.com/result")
+                        .queue_timeout_url("https://example.com/timeout"),
+                )
+                .transport(FakeDaraja::new(status_query_accepted))
+                .store(MemoryStore::default())
+                .build();
+            let stale = Utc::now() - chrono::Duration::minutes(10);
+            client.store().insert(record(TransactionKind::B2c, "AG_1", stale)).await.unwrap();
+
+            deliver(&client, status_result("AG_1", "Pending"));
+            let settled = Settled::default();
+            let mut recovery = B2cRecovery::new(client.clone(), settled.clone());
+            recovery.recover().await.unwrap();
+
+            assert!(settled.records().is_empty());
+            let pending = client.store().pending(TransactionKind::B2c).await.unwrap();
+            assert_eq!(request_ids(&pending), ["AG_1"]);
         }
 
-        /// Initiates a B2C payment to a customer's phone number.
-        ///
-        /// # Arguments
-        /// * `phone_number` - Recipient's phone number (e.g., "2547XXXXXXXX").
-        /// * `amount` - Amount to send in KES.
-        /// * `remarks` - Transaction remarks.
-        /// * `result_url` - URL to receive the result callback.
-        /// * `queue_timeout_url` - URL for timeout notifications.
-        /// * `initiator_name` - The initiator username.
-        /// * `security_credential` - Generated security credential.
-        /// * `short_code` - Business shortcode.
-        /// * `occasion` - Optional occasion description.
-        pub async fn business_payment(
-            &self,
-            phone_number: &str,
-            amount: u32,
-            remarks: &str,
-            result_url: &str,
-            queue_timeout_url: &str,
-            initiator_name: &str,
-            security_credential: &str,
-            short_code: &str,
-            occasion: &str,
-        ) -> Result<B2cResponse, Box<dyn Error>> {
-            let client = Client::new();
-            let access_token = self.get_access_token().await?;
-            let url = if self.environment == "sandbox" {
-                "https://sandbox.safaricom.co.ke/mpesa/b2c/v1/paymentrequest"
-            } else {
-                "https://api.safaricom.co.ke/mpesa/b2c/v1/paymentrequest"
-            };
+        #[tokio::test]
+        async fn recovery_marks_payments_it_gives_up_on_unresolved() {
+            let client = MpesaClient::builder("key", "secret")
+                .profile(
+                    BusinessProfile::default()
+                        .initiator_name("testapi")
+                        .security_credential("credential")
+                        .result_url("https://example.com/result")
+                        .queue_timeout_url("https://example.com/timeout"),
+                )
+                .transport(FakeDaraja::new(status_query_accepted))
+                .store(MemoryStore::default())
+                .build();
+            let stale = Utc::now() - chrono::Duration::minutes(10);
+            client.store().insert(record(TransactionKind::B2c, "AG_1", stale)).await.unwrap();
 
-            let request_body = B2cRequest {
-                initiator_name: initiator_name.to_string(),
-                security_credential: security_credential.to_string(),
-                command_id: "BusinessPayment".to_string(),
-                amount: amount.to_string(),
-                party_a: short_code.to_string(),
-                party_b: phone_number.to_string(),
-                remarks: remarks.to_string(),
-                queue_timeout_url: queue_timeout_url.to_string(),
-                result_url: result_url.to_string(),
-                occasion: occasion.to_string(),
+            deliver(&client, status_result("AG_1", "Pending"));
+            let settled = Settled::default();
+            let mut recovery = B2cRecovery::new(client.clone(), settled.clone()).max_attempts(1);
+            recovery.recover().await.unwrap();
+
+            assert!(settled.records().is_empty());
+            let record = client.store().get("AG_1").await.unwrap().unwrap();
+            assert_eq!(record.status, TransactionStatus::Unresolved);
+            assert!(client.store().pending(TransactionKind::B2c).await.unwrap().is_empty());
+        }
+
+        #[tokio::test]
+        async fn recovery_keeps_a_result_that_lands_during_its_query() {
+            let store = MemoryStore::default();
+            let racing = store.clone();
+            let client = MpesaClient::builder("key", "secret")
+                .profile(
+                    BusinessProfile::default()
+                        .initiator_name("testapi")
+                        .security_credential("credential")
+                        .result_url("https://example.com/result")
+                        .queue_timeout_url("https://example.com/timeout"),
+                )
+                .transport(FakeDaraja::new(move |request| {
+                    // The real B2C result is recorded while the query is in flight.
+                    let update = TransactionUpdate {
+                        status: TransactionStatus::Completed,
+                        result_code: Some(0),
+                        result_desc: Some("The service request is processed successfully.".to_string()),
+                        receipt: Some("NLJ41HAY6Q".to_string()),
+                    };
+                    racing.apply("AG_1", update, false);
+                    status_query_accepted(request)
+                }))
+                .store(store)
+                .build();
+            let stale = Utc::now() - chrono::Duration::minutes(10);
+            client.store().insert(record(TransactionKind::B2c, "AG_1", stale)).await.unwrap();
+
+            deliver(&client, status_result("AG_1", "Completed"));
+            let settled = Settled::default();
+            B2cRecovery::new(client.clone(), settled.clone()).recover().await.unwrap();
+
+            assert!(settled.records().is_empty());
+            let record = client.store().get("AG_1").await.unwrap().unwrap();
+            assert_eq!(record.receipt.as_deref(), Some("NLJ41HAY6Q"));
+            assert_eq!(record.result_desc.as_deref(), Some("The service request is processed successfully."));
+        }
+
+        #[tokio::test]
+        async fn recovery_refuses_a_client_without_a_store() {
+            let client = MpesaClient::builder("key", "secret")
+                .transport(FakeDaraja::new(status_query_accepted))
+                .build();
+
+            let mut recovery = B2cRecovery::new(client, Settled::default());
+            assert!(matches!(recovery.recover().await, Err(MpesaError::Config(_))));
+        }
+
+        #[tokio::test]
+        async fn stk_push_query_falls_back_to_the_profile() {
+            let daraja = FakeDaraja::new(|_| stk_push_queried("0", "The service request is processed successfully."));
+            let client = MpesaClient::builder("key", "secret")
+                .profile(BusinessProfile::default().short_code("174379").passkey("passkey"))
+                .transport(daraja.clone())
+                .build();
+
+            assert!(client.stk_push_query("ws_CO_1", None, None).await.unwrap().is_paid());
+            let sent = daraja.sent("/stkpushquery/");
+            let body: serde_json::Value = serde_json::from_str(sent[0].body.as_deref().unwrap()).unwrap();
+            assert_eq!(body["BusinessShortCode"], "174379");
+            assert_eq!(body["CheckoutRequestID"], "ws_CO_1");
+
+            let client = MpesaClient::builder("key", "secret").transport(daraja).build();
+            let Err(MpesaError::InvalidRequest(message)) = client.stk_push_query("ws_CO_1", None, None).await else {
+                panic!("STK Push Query sent without a shortcode or passkey");
             };
+            assert!(message.contains("short_code, passkey"), "{}", message);
+        }
 
-            let response = client
-                .post(url)
-                .header("Authorization", format!("Bearer {}", access_token))
-                .json(&request_body)
-                .send()
-                .await?;
+        /// Records which handler method each callback reached.
+        #[cfg(feature = "server")]
+        #[derive(Clone, Default)]
+        struct Dispatched(Arc<std::sync::Mutex<Vec<String>>>);
+
+        #[cfg(feature = "server")]
+        impl Dispatched {
+            fn calls(&self) -> Vec<String> {
+                self.0.lock().unwrap().clone()
+            }
+
+            fn push(&self, call: String) {
+                self.0.lock().unwrap().push(call);
+            }
+        }
+
+        #[cfg(feature = "server")]
+        impl CallbackHandler for Dispatched {
+            async fn on_stk_callback(&self, callback: StkCallback) {
+                self.push(format!("stk {}", callback.checkout_request_id));
+            }
+
+            async fn on_b2c_result(&self, result: B2cResult) {
+                self.push(format!("b2c result {}", result.originator_conversation_id));
+            }
 
-            let text = response.text().await?;
-            if text.contains("errorCode") {
-                let error: ErrorResponse = serde_json::from_str(&text)?;
-                return Err(format!("API Error: {} - {}", error.error_code, error.error_message).into());
+            async fn on_b2c_timeout(&self, payload: serde_json::Value) {
+                let id = payload["OriginatorConversationID"].as_str().unwrap_or_default();
+                self.push(format!("b2c timeout {}", id));
             }
 
-            let b2c_response: B2cResponse = serde_json::from_str(&text)?;
-            Ok(b2c_response)
+            async fn on_balance_result(&self, result: BalanceResponse) {
+                self.push(format!("balance {}", result.originator_conversation_id));
+            }
+
+            async fn on_transaction_status_result(&self, result: TransactionStatusResult) {
+                self.push(format!("status {}", result.originator_conversation_id));
+            }
         }
 
-        /// Queries the account balance for a shortcode.
-        ///
-        /// # Arguments
-        /// * `initiator_name` - The initiator username.
-        /// * `security_credential` - Generated security credential.
-        /// * `short_code` - Business shortcode.
-        /// * `remarks` - Request remarks.
-        /// * `queue_timeout_url` - URL for timeout notifications.
-        /// * `result_url` - URL to receive the result callback.
-        pub async fn check_balance(
-            &self,
-            initiator_name: &str,
-            security_credential: &str,
-            short_code: &str,
-            remarks: &str,
-            queue_timeout_url: &str,
-            result_url: &str,
-        ) -> Result<BalanceQueryResponse, Box<dyn Error>> {
-            let client = Client::new();
-            let access_token = self.get_access_token().await?;
-            let url = if self.environment == "sandbox" {
-                "https://sandbox.safaricom.co.ke/mpesa/accountbalance/v1/query"
-            } else {
-                "https://api.safaricom.co.ke/mpesa/accountbalance/v1/query"
+        /// Posts `body` to `path` on `app`, returning the status and decoded reply.
+        #[cfg(feature = "server")]
+        async fn post_callback(
+            app: axum::Router,
+            path: &str,
+            body: String,
+        ) -> (axum::http::StatusCode, serde_json::Value) {
+            use tower::ServiceExt;
+
+            let request = axum::http::Request::post(path)
+                .header("content-type", "application/json")
+                .body(axum::body::Body::from(body))
+                .unwrap();
+            let response = app.oneshot(request).await.unwrap();
+            let status = response.status();
+            let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+            (status, serde_json::from_slice(&body).unwrap())
+        }
+
+        #[cfg(feature = "server")]
+        fn stk_callback_payload(checkout_request_id: &str) -> String {
+            json!({"Body": {"stkCallback": {
+                "MerchantRequestID": "29115-34620561-1",
+                "CheckoutRequestID": checkout_request_id,
+                "ResultCode": 1032,
+                "ResultDesc": "Request cancelled by user"
+            }}})
+            .to_string()
+        }
+
+        #[cfg(feature = "server")]
+        fn result_payload(originator_conversation_id: &str) -> String {
+            json!({"Result": {
+                "ResultType": 0,
+                "ResultCode": 2001,
+                "ResultDesc": "The initiator information is invalid.",
+                "OriginatorConversationID": originator_conversation_id,
+                "ConversationID": "AG_20191219_00004e48cf7e3533f581",
+                "TransactionID": "NLJ41HAY6Q"
+            }})
+            .to_string()
+        }
+
+        #[cfg(feature = "server")]
+        #[tokio::test]
+        async fn router_dispatches_each_route_to_its_handler_method() {
+            let dispatched = Dispatched::default();
+            let app: axum::Router =
+                server::router(dispatched.clone(), server::CallbackPaths::default());
+            let posts = [
+                ("/mpesa/stk/callback", stk_callback_payload("ws_CO_1")),
+                ("/mpesa/b2c/result", result_payload("b2c-1")),
+                ("/mpesa/b2c/timeout", json!({"OriginatorConversationID": "b2c-2"}).to_string()),
+                ("/mpesa/balance/result", result_payload("balance-1")),
+                ("/mpesa/status/result", result_payload("status-1")),
+            ];
+            for (path, body) in posts {
+                let (status, reply) = post_callback(app.clone(), path, body).await;
+                assert_eq!(status, axum::http::StatusCode::OK, "{}", path);
+                assert_eq!(reply, json!({"ResultCode": 0, "ResultDesc": "Accepted"}));
+            }
+
+            assert_eq!(
+                dispatched.calls(),
+                [
+                    "stk ws_CO_1",
+                    "b2c result b2c-1",
+                    "b2c timeout b2c-2",
+                    "balance balance-1",
+                    "status status-1",
+                ]
+            );
+        }
+
+        #[cfg(feature = "server")]
+        #[tokio::test]
+        async fn router_rejects_undecodable_payloads() {
+            let dispatched = Dispatched::default();
+            let app: axum::Router =
+                server::router(dispatched.clone(), server::CallbackPaths::default());
+            for path in ["/mpesa/stk/callback", "/mpesa/b2c/result", "/mpesa/balance/result"] {
+                let (status, reply) =
+                    post_callback(app.clone(), path, json!({"Body": {}}).to_string()).await;
+                assert_eq!(status, axum::http::StatusCode::BAD_REQUEST, "{}", path);
+                assert_eq!(reply["ResultCode"], 1);
+            }
+            let garbage = "not json".to_string();
+            let (status, _) = post_callback(app, "/mpesa/status/result", garbage).await;
+            assert_eq!(status, axum::http::StatusCode::BAD_REQUEST);
+            assert!(dispatched.calls().is_empty());
+        }
+
+        #[cfg(feature = "server")]
+        #[tokio::test]
+        async fn router_mounts_routes_on_custom_paths() {
+            let dispatched = Dispatched::default();
+            let paths = server::CallbackPaths::default()
+                .stk("/hooks/stk")
+                .transaction_status_result("/hooks/status");
+            let app: axum::Router = server::router(dispatched.clone(), paths);
+
+            let stk = stk_callback_payload("ws_CO_1");
+            let (status, _) = post_callback(app.clone(), "/hooks/stk", stk).await;
+            assert_eq!(status, axum::http::StatusCode::OK);
+            let result = result_payload("status-1");
+            let (status, _) = post_callback(app.clone(), "/hooks/status", result).await;
+            assert_eq!(status, axum::http::StatusCode::OK);
+            let request = axum::http::Request::post("/mpesa/stk/callback")
+                .body(axum::body::Body::from(stk_callback_payload("ws_CO_2")))
+                .unwrap();
+            let response = tower::ServiceExt::oneshot(app, request).await.unwrap();
+            assert_eq!(response.status(), axum::http::StatusCode::NOT_FOUND);
+
+            assert_eq!(dispatched.calls(), ["stk ws_CO_1", "status status-1"]);
+        }
+
+        /// Writes `contents` to a file named `name` in a directory unique to this run.
+        fn config_file(name: &str, contents: &str) -> std::path::PathBuf {
+            let dir = std::env::temp_dir().join(format!("mpesa-config-{}", std::process::id()));
+            fs::create_dir_all(&dir).unwrap();
+            let path = dir.join(name);
+            fs::write(&path, contents).unwrap();
+            path
+        }
+
+        #[test]
+        fn config_names_every_missing_variable() {
+            let Err(MpesaError::Config(message)) = MpesaConfig::default()
+                .into_client(|field| format!("{}{}", ENV_PREFIX, field.to_uppercase()))
+            else {
+                panic!("an empty config built a client");
             };
+            assert_eq!(message, "missing MPESA_CONSUMER_KEY, MPESA_CONSUMER_SECRET");
 
-            let request_body = BalanceRequest {
-                initiator: initiator_name.to_string(),
-                security_credential: security_credential.to_string(),
-                command_id: "AccountBalance".to_string(),
-                party_a: short_code.to_string(),
-                identifier_type: "4".to_string(),
-                remarks: remarks.to_string(),
-                queue_timeout_url: queue_timeout_url.to_string(),
-                result_url: result_url.to_string(),
+            let path = config_file("partial.json", r#"{"consumer_key": "key"}"#);
+            let Err(MpesaError::Config(message)) = MpesaClient::from_config(&path) else {
+                panic!("a config without a secret built a client");
             };
+            assert_eq!(message, "missing consumer_secret");
+        }
 
-            let response = client
-                .post(url)
-                .header("Authorization", format!("Bearer {}", access_token))
-                .json(&request_body)
-                .send()
-                .await?;
+        #[test]
+        fn config_is_read_from_toml_and_json() {
+            let toml = config_file(
+                "mpesa.toml",
+                r#"
+                consumer_key = "key"
+                consumer_secret = "secret"
+                environment = "production"
+                short_code = "600000"
+                result_url = "https://example.com/result"
+                "#,
+            );
+            let client = MpesaClient::from_config(&toml).unwrap();
+            assert_eq!(client.inner.consumer_key, "key");
+            assert_eq!(client.inner.environment, Environment::Production);
+            assert_eq!(client.inner.profile.short_code.as_deref(), Some("600000"));
+            let result_url = client.inner.profile.result_url.as_deref();
+            assert_eq!(result_url, Some("https://example.com/result"));
 
-            let text = response.text().await?;
-            let balance_response: BalanceQueryResponse = serde_json::from_str(&text)?;
-            Ok(balance_response)
+            let json = config_file(
+                "mpesa.json",
+                r#"{"consumer_key": "key", "consumer_secret": "secret", "passkey": "passkey"}"#,
+            );
+            let client = MpesaClient::from_config(&json).unwrap();
+            assert_eq!(client.inner.environment, Environment::Sandbox);
+            assert_eq!(client.inner.profile.passkey.as_deref(), Some("passkey"));
+            assert_eq!(client.inner.profile.short_code, None);
         }
 
-        /// Checks the status of a previous transaction.
-        ///
-        /// # Arguments
-        /// * `initiator_name` - The initiator username.
-        /// * `security_credential` - Generated security credential.
-        /// * `transaction_id` - The transaction ID to query.
-        /// * `short_code` - Business shortcode.
-        /// * `remarks` - Request remarks.
-        /// * `result_url` - URL to receive the result callback.
-        /// * `queue_timeout_url` - URL for timeout notifications.
-        /// * `occasion` - Optional occasion description.
-        pub async fn check_transaction_status(
-            &self,
-            initiator_name: &str,
-            security_credential: &str,
-            transaction_id: &str,
-            short_code: &str,
-            remarks: &str,
-            result_url: &str,
-            queue_timeout_url: &str,
-            occasion: &str,
-        ) -> Result<TransactionStatusResponse, Box<dyn Error>> {
-            let client = Client::new();
-            let access_token = self.get_access_token().await?;
-            let url = if self.environment == "sandbox" {
-                "https://sandbox.safaricom.co.ke/mpesa/transactionstatus/v1/query"
-            } else {
-                "https://api.safaricom.co.ke/mpesa/transactionstatus/v1/query"
+        #[test]
+        fn config_rejects_unknown_keys_and_extensions() {
+            let toml = config_file(
+                "typo.toml",
+                "consumer_key = \"key\"\nconsumer_secert = \"secret\"\n",
+            );
+            let Err(MpesaError::Config(message)) = MpesaClient::from_config(&toml) else {
+                panic!("a misspelt key was accepted");
             };
+            assert!(message.contains("consumer_secert"), "{}", message);
 
-            let request_body = TransactionStatusRequest {
-                initiator: initiator_name.to_string(),
-                security_credential: security_credential.to_string(),
-                command_id: "TransactionStatusQuery".to_string(),
-                transaction_id: transaction_id.to_string(),
-                party_a: short_code.to_string(),
-                identifier_type: "4".to_string(),
-                result_url: result_url.to_string(),
-                queue_timeout_url: queue_timeout_url.to_string(),
-                remarks: remarks.to_string(),
-                occasion: occasion.to_string(),
+            let json =
+                config_file("typo.json", r#"{"consumer_key": "key", "shortcode": "600000"}"#);
+            let Err(MpesaError::Config(message)) = MpesaClient::from_config(&json) else {
+                panic!("a misspelt key was accepted");
             };
+            assert!(message.contains("shortcode"), "{}", message);
 
-            let response = client
-                .post(url)
-                .header("Authorization", format!("Bearer {}", access_token))
-                .json(&request_body)
-                .send()
-                .await?;
+            let yaml = config_file("mpesa.yaml", "consumer_key: key\n");
+            assert!(matches!(MpesaClient::from_config(&yaml), Err(MpesaError::Config(_))));
+        }
+
+        #[test]
+        fn environment_parses_names_and_base_urls_but_not_typos() {
+            assert_eq!(" Sandbox ".parse::<Environment>().unwrap(), Environment::Sandbox);
+            assert_eq!("PRODUCTION".parse::<Environment>().unwrap(), Environment::Production);
+            let custom = "https://daraja.example.com/".parse::<Environment>().unwrap();
+            assert_eq!(custom.base_url(), "https://daraja.example.com");
+            for typo in ["prod", "prodution", "live", ""] {
+                let parsed = typo.parse::<Environment>();
+                assert!(matches!(parsed, Err(MpesaError::Config(_))), "{:?}: {:?}", typo, parsed);
+            }
+        }
+
+        #[tokio::test]
+        async fn simulate_c2b_is_refused_in_production() {
+            let daraja = FakeDaraja::new(|_| {
+                let body = json!({
+                    "OriginatorCoversationID": "53e3-4aa8-9fe0-8fb5e4092cdd3405976",
+                    "ResponseCode": "0",
+                    "ResponseDescription": "Accept the service request successfully."
+                });
+                (StatusCode::OK, body)
+            });
+            let profile = BusinessProfile::default().short_code("600984");
+            let production = MpesaClient::builder("key", "secret")
+                .environment(Environment::Production)
+                .profile(profile.clone())
+                .transport(daraja.clone())
+                .build();
+            let paybill = C2bCommand::CustomerPayBillOnline;
+            let simulated = production
+                .simulate_c2b(None, paybill, 10, "254708374149", Some("INV-1"))
+                .await;
+            assert!(matches!(simulated, Err(MpesaError::InvalidRequest(_))));
+            assert!(daraja.sent("").is_empty());
+
+            let sandbox = MpesaClient::builder("key", "secret")
+                .profile(profile)
+                .transport(daraja.clone())
+                .build();
+            let response = sandbox
+                .simulate_c2b(None, C2bCommand::CustomerBuyGoodsOnline, 10, "254708374149", None)
+                .await
+                .unwrap();
+            assert_eq!(response.response_code, "0");
+            let sent = daraja.sent("/c2b/v1/simulate");
+            let payload: serde_json::Value =
+                serde_json::from_str(sent[0].body.as_deref().unwrap()).unwrap();
+            assert_eq!(payload["ShortCode"], "600984");
+        }
+
+        fn missing_fields<R>(built: Result<R, MpesaError>) -> String {
+            match built {
+                Err(MpesaError::InvalidRequest(message)) => message,
+                Err(err) => panic!("unexpected error: {}", err),
+                Ok(_) => panic!("an incomplete request was built"),
+            }
+        }
+
+        #[test]
+        fn builders_name_every_missing_field() {
+            let stk = StkPushRequest::builder().amount(10).build();
+            assert_eq!(
+                missing_fields(stk),
+                "missing required fields: phone_number, account_reference, transaction_desc"
+            );
+            let b2b = B2bRequest::builder().receiver_short_code("000000").build();
+            assert_eq!(
+                missing_fields(b2b),
+                "missing required fields: command, amount, account_reference, remarks"
+            );
+            let balance = BalanceRequest::builder().build();
+            assert_eq!(missing_fields(balance), "missing required fields: remarks");
 
-            let text = response.text().await?;
-            let status_response: TransactionStatusResponse = serde_json::from_str(&text)?;
-            Ok(status_response)
+            let complete = StkPushRequest::builder()
+                .phone_number("254712345678")
+                .amount(10)
+                .account_reference("INV-1")
+                .transaction_desc("Invoice")
+                .build();
+            assert!(complete.is_ok());
         }
     }
 }